@@ -0,0 +1,256 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse::ParseStream, parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields, Ident,
+    LitByteStr, LitChar, Token,
+};
+
+/// Derives `utils::GridCell`, `TryFrom<u8>` and `Display` for a grid cell type.
+///
+/// Each enum variant is tagged with the character it is read from and drawn
+/// as, e.g. `#[cell('#')]`. Variants carrying data are built from
+/// `Default::default()`. Adding `default` (`#[cell('.', default)]`) also
+/// derives `Default`. A single-field tuple struct can instead take a range,
+/// `#[cell('0'..='9')]`, storing the offset from the start of the range.
+///
+/// `#[grid_cell(no_display)]` on the type skips the `Display` impl, for
+/// cells that render themselves differently.
+#[proc_macro_derive(GridCell, attributes(cell, grid_cell))]
+pub fn derive_grid_cell(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct CellAttr {
+    first: u8,
+    last: Option<u8>,
+    default: bool,
+}
+
+fn ascii(lit: &LitChar) -> syn::Result<u8> {
+    let c = lit.value();
+    if c.is_ascii() {
+        Ok(c as u8)
+    } else {
+        Err(syn::Error::new(
+            lit.span(),
+            "grid cell characters must be ASCII",
+        ))
+    }
+}
+
+fn parse_cell_attr(input: ParseStream) -> syn::Result<CellAttr> {
+    let first: LitChar = input.parse()?;
+    let mut attr = CellAttr {
+        first: ascii(&first)?,
+        last: None,
+        default: false,
+    };
+    if input.peek(Token![..=]) {
+        input.parse::<Token![..=]>()?;
+        let last: LitChar = input.parse()?;
+        let last_byte = ascii(&last)?;
+        if last_byte < attr.first {
+            return Err(syn::Error::new(last.span(), "empty character range"));
+        }
+        attr.last = Some(last_byte);
+    }
+    if input.peek(Token![,]) {
+        input.parse::<Token![,]>()?;
+        let flag: Ident = input.parse()?;
+        if flag != "default" {
+            return Err(syn::Error::new(flag.span(), "expected `default`"));
+        }
+        attr.default = true;
+    }
+    Ok(attr)
+}
+
+fn cell_attr(attrs: &[syn::Attribute], span: proc_macro2::Span) -> syn::Result<CellAttr> {
+    let mut found = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("cell")) {
+        if found.is_some() {
+            return Err(syn::Error::new(attr.span(), "duplicate #[cell] attribute"));
+        }
+        found = Some(attr.parse_args_with(parse_cell_attr)?);
+    }
+    found.ok_or_else(|| syn::Error::new(span, "missing #[cell('x')] attribute"))
+}
+
+fn no_display(attrs: &[syn::Attribute]) -> syn::Result<bool> {
+    let mut skip = false;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("grid_cell")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("no_display") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown grid_cell option"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (chars, to_byte, try_from, default) = match &input.data {
+        Data::Enum(data) => expand_enum(name, data)?,
+        Data::Struct(data) => expand_struct(input, data)?,
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                input.span(),
+                "GridCell cannot be derived for unions",
+            ))
+        }
+    };
+    let chars = LitByteStr::new(&chars, name.span());
+
+    let display = if no_display(&input.attrs)? {
+        quote!()
+    } else {
+        quote! {
+            impl ::std::fmt::Display for #name {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    write!(f, "{}", ::utils::GridCell::to_byte(self) as char)
+                }
+            }
+        }
+    };
+    let default = default.map(|body| {
+        quote! {
+            impl ::std::default::Default for #name {
+                fn default() -> Self {
+                    #body
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl ::utils::GridCell for #name {
+            const CHARS: &'static [u8] = #chars;
+
+            fn to_byte(&self) -> u8 {
+                #to_byte
+            }
+        }
+
+        impl ::std::convert::TryFrom<u8> for #name {
+            type Error = ::utils::CellError;
+
+            fn try_from(value: u8) -> ::std::result::Result<Self, Self::Error> {
+                #try_from
+            }
+        }
+
+        #display
+        #default
+    })
+}
+
+type Expansion = (Vec<u8>, TokenStream2, TokenStream2, Option<TokenStream2>);
+
+fn expand_enum(name: &Ident, data: &syn::DataEnum) -> syn::Result<Expansion> {
+    let mut chars: Vec<u8> = vec![];
+    let mut to_byte = vec![];
+    let mut from_byte = vec![];
+    let mut default = None;
+
+    for variant in data.variants.iter() {
+        let attr = cell_attr(&variant.attrs, variant.ident.span())?;
+        if attr.last.is_some() {
+            return Err(syn::Error::new(
+                variant.span(),
+                "character ranges are only supported on tuple structs",
+            ));
+        }
+        let b = attr.first;
+        if chars.contains(&b) {
+            return Err(syn::Error::new(
+                variant.span(),
+                format!("character {:?} is used by more than one variant", b as char),
+            ));
+        }
+        chars.push(b);
+
+        let ident = &variant.ident;
+        let (pattern, build) = match &variant.fields {
+            Fields::Unit => (quote!(#name::#ident), quote!(#name::#ident)),
+            Fields::Unnamed(fields) => {
+                let defaults = fields
+                    .unnamed
+                    .iter()
+                    .map(|_| quote!(::std::default::Default::default()));
+                (
+                    quote!(#name::#ident(..)),
+                    quote!(#name::#ident(#(#defaults),*)),
+                )
+            }
+            Fields::Named(fields) => {
+                let defaults = fields.named.iter().map(|f| {
+                    let f = &f.ident;
+                    quote!(#f: ::std::default::Default::default())
+                });
+                (
+                    quote!(#name::#ident { .. }),
+                    quote!(#name::#ident { #(#defaults),* }),
+                )
+            }
+        };
+        to_byte.push(quote!(#pattern => #b));
+        from_byte.push(quote!(#b => ::std::result::Result::Ok(#build)));
+        if attr.default {
+            if default.is_some() {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "only one variant can be the default",
+                ));
+            }
+            default = Some(build);
+        }
+    }
+
+    Ok((
+        chars,
+        quote! {
+            match self {
+                #(#to_byte,)*
+            }
+        },
+        quote! {
+            match value {
+                #(#from_byte,)*
+                _ => ::std::result::Result::Err(::utils::CellError::new::<Self>(value)),
+            }
+        },
+        default,
+    ))
+}
+
+fn expand_struct(input: &DeriveInput, data: &syn::DataStruct) -> syn::Result<Expansion> {
+    if !matches!(&data.fields, Fields::Unnamed(f) if f.unnamed.len() == 1) {
+        return Err(syn::Error::new(
+            input.span(),
+            "GridCell structs must have exactly one unnamed field",
+        ));
+    }
+    let attr = cell_attr(&input.attrs, input.ident.span())?;
+    let (first, last) = (attr.first, attr.last.unwrap_or(attr.first));
+    let default = attr.default.then(|| quote!(Self(0)));
+
+    Ok((
+        (first..=last).collect(),
+        quote!(#first + self.0),
+        quote! {
+            match value {
+                #first..=#last => ::std::result::Result::Ok(Self(value - #first)),
+                _ => ::std::result::Result::Err(::utils::CellError::new::<Self>(value)),
+            }
+        },
+        default,
+    ))
+}
@@ -1,89 +1,178 @@
-use std::collections::{HashMap, HashSet};
+use rand::prelude::*;
+use utils::{BasicGrid, Coord, Dir, GridCell};
 
-use utils::{BasicGrid, Coord, Dir};
-
-#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, GridCell)]
+#[cell('0'..='9')]
 struct Level(u8);
 
-impl Level {
-    fn inc(&self) -> Self {
-        Level(self.0 + 1)
-    }
+const TRAILHEAD: Level = Level(0);
+const PEAK: Level = Level(9);
+
+/// Which neighbouring levels a trail may step onto. Every rule climbs, so
+/// trails never revisit a cell.
+#[derive(Clone, Copy, Debug)]
+enum Step {
+    /// exactly one level up (the puzzle's rule)
+    PlusOne,
+    /// any higher level
+    Ascent,
+    /// up by at least one and at most `k` levels
+    UpTo(u8),
 }
-impl From<u8> for Level {
-    fn from(value: u8) -> Self {
-        assert!(value > 47 && value < 58);
-        Self(value - 48)
+
+impl Step {
+    fn allows(self, from: Level, to: Level) -> bool {
+        match self {
+            Step::PlusOne => to.0 == from.0 + 1,
+            Step::Ascent => to.0 > from.0,
+            Step::UpTo(k) => to.0 > from.0 && to.0 - from.0 <= k,
+        }
     }
 }
 
-struct Part1 {
-    grid: BasicGrid<Level>,
+/// Trail counts for every cell, filled in from the peaks down so each cell
+/// is visited once.
+struct Trails<'a> {
+    grid: &'a BasicGrid<Level>,
+    step: Step,
+    /// distinct trails from each cell to any peak
+    ratings: BasicGrid<u64>,
+    /// peaks reachable from each cell, as a bitset over `peaks`
+    reach: BasicGrid<Box<[u64]>>,
+    peaks: Vec<Coord>,
 }
 
-impl Part1 {
-    fn trace_from(&self, start: Coord) -> HashSet<Coord> {
-        let this_level = self.grid.at(start);
-        let mut nines: HashSet<Coord> = Default::default();
-        if this_level.0 == 9 {
-            nines.insert(start);
-            return nines;
+impl<'a> Trails<'a> {
+    fn new(grid: &'a BasicGrid<Level>, step: Step) -> Self {
+        let peaks = grid.find_with(|l| *l == PEAK);
+        let words = peaks.len().div_ceil(64);
+        let mut ratings: BasicGrid<u64> = BasicGrid::from_fn(grid.width, grid.height, |_| 0);
+        let mut reach: BasicGrid<Box<[u64]>> =
+            BasicGrid::from_fn(grid.width, grid.height, |_| vec![0; words].into());
+        for (i, p) in peaks.iter().enumerate() {
+            ratings[*p] = 1;
+            reach[*p][i / 64] |= 1 << (i % 64);
         }
-        let target = this_level.inc();
-        //println!("tracing from {:?} [{:?}]", start, target);
-        for dir in [Dir::Up, Dir::Down, Dir::Left, Dir::Right] {
-            if let Some(l) = self.grid.get(start, dir, 1) {
-                if l == target {
-                    let from_here = self.trace_from(start.in_dir(dir).unwrap());
-                    //println!("from {:?} in direction {:?} is {:?}", start, dir, from_here);
-                    nines.extend(from_here);
+
+        let mut cells = grid.find_with(|l| *l != PEAK);
+        cells.sort_by_key(|c| std::cmp::Reverse(grid[*c].0));
+        let mut trails = Self {
+            grid,
+            step,
+            ratings,
+            reach,
+            peaks,
+        };
+        for c in cells {
+            let mut rating: u64 = 0;
+            let mut reach = vec![0; words];
+            for n in trails.neighbours(c) {
+                rating = rating
+                    .checked_add(trails.ratings[n])
+                    .expect("trail count overflows u64");
+                for (a, b) in reach.iter_mut().zip(trails.reach[n].iter()) {
+                    *a |= b;
                 }
-            };
+            }
+            trails.ratings[c] = rating;
+            trails.reach[c] = reach.into();
         }
-        //        println!("{} nines from {:?} at {:?}", nines.len(), this_level, start);
-        nines
+        trails
     }
-}
 
-struct Part2 {
-    grid: BasicGrid<Level>,
-}
+    /// Cells a trail may step onto from `pos`.
+    fn neighbours(&self, pos: Coord) -> impl Iterator<Item = Coord> + '_ {
+        Dir::ALL
+            .into_iter()
+            .filter_map(move |d| self.grid.next_pos(pos, d))
+            .filter(move |n| self.step.allows(self.grid[pos], self.grid[*n]))
+    }
+
+    fn trailheads(&self) -> Vec<Coord> {
+        self.grid.find_with(|l| *l == TRAILHEAD)
+    }
+
+    /// Number of distinct peaks reachable from `pos`.
+    fn score(&self, pos: Coord) -> u64 {
+        self.reach[pos].iter().map(|w| w.count_ones() as u64).sum()
+    }
+
+    /// Number of distinct trails from `pos` to any peak.
+    fn rating(&self, pos: Coord) -> u64 {
+        self.ratings[pos]
+    }
+
+    fn peaks_from(&self, pos: Coord) -> Vec<Coord> {
+        self.peaks
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| self.reach[pos][i / 64] & (1 << (i % 64)) != 0)
+            .map(|(_, p)| *p)
+            .collect()
+    }
 
-impl Part2 {
-    fn trace_from(&self, start: Coord) -> u16 {
-        let this_level = self.grid.at(start);
-        if this_level.0 == 9 {
-            return 1;
+    fn total_score(&self) -> u64 {
+        self.trailheads().iter().map(|t| self.score(*t)).sum()
+    }
+
+    fn total_rating(&self) -> u64 {
+        self.trailheads().iter().map(|t| self.rating(*t)).sum()
+    }
+
+    /// The `k`th trail from `pos`, counting in `Dir::ALL` order at each
+    /// step, or `None` if there are no more than `k` trails.
+    fn nth_trail(&self, pos: Coord, mut k: u64) -> Option<Vec<Coord>> {
+        if k >= self.rating(pos) {
+            return None;
         }
-        let mut cnt = 0;
-        let target = this_level.inc();
-        //println!("tracing from {:?} [{:?}]", start, target);
-        for dir in [Dir::Up, Dir::Down, Dir::Left, Dir::Right] {
-            if let Some(l) = self.grid.get(start, dir, 1) {
-                if l == target {
-                    let from_here = self.trace_from(start.in_dir(dir).unwrap());
-                    //println!("from {:?} in direction {:?} is {:?}", start, dir, from_here);
-                    cnt += from_here;
+        let mut trail = vec![pos];
+        let mut cur = pos;
+        while self.grid[cur] != PEAK {
+            for n in self.neighbours(cur) {
+                if k < self.ratings[n] {
+                    cur = n;
+                    break;
                 }
-            };
+                k -= self.ratings[n];
+            }
+            trail.push(cur);
+        }
+        Some(trail)
+    }
+
+    /// Every trail from `pos`, lazily.
+    fn trails(&self, pos: Coord) -> impl Iterator<Item = Vec<Coord>> + '_ {
+        (0..self.rating(pos)).map(move |k| self.nth_trail(pos, k).unwrap())
+    }
+
+    /// A trail from `pos` chosen uniformly among all of them.
+    fn sample<R: Rng>(&self, pos: Coord, rng: &mut R) -> Option<Vec<Coord>> {
+        match self.rating(pos) {
+            0 => None,
+            n => self.nth_trail(pos, rng.random_range(0..n)),
         }
-        //        println!("{} nines from {:?} at {:?}", nines.len(), this_level, start);
-        cnt
     }
 }
+
 fn main() {
     //let input: Vec<&str> = TEST.lines().collect();
     let input = std::fs::read_to_string("input/d10.txt").unwrap();
     let input: Vec<&str> = input.lines().collect();
     let grid: BasicGrid<Level> = BasicGrid::new(&input);
-    let part2 = Part2 { grid };
-    let starts = part2.grid.find_with(|v| *v == Level(0));
-    let mut stats: HashMap<Coord, u16> = Default::default();
+    let trails = Trails::new(&grid, Step::PlusOne);
+    //let trails = Trails::new(&grid, Step::UpTo(2));
+    println!("{}", trails.total_score());
+    println!("{}", trails.total_rating());
 
-    for start in starts {
-        stats.insert(start, part2.trace_from(start));
+    let mut rng = rand::rng();
+    if let Some(start) = trails.trailheads().first() {
+        println!(
+            "{} peaks from {:?}, e.g. {:?}",
+            trails.peaks_from(*start).len(),
+            start,
+            trails.sample(*start, &mut rng)
+        );
     }
-    println!("{:?}", stats.into_values().sum::<u16>());
 }
 
 static TEST: &str = "89010123
@@ -94,3 +183,34 @@ static TEST: &str = "89010123
 32019012
 01329801
 10456732";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_trails() {
+        let input: Vec<&str> = TEST.lines().collect();
+        let grid: BasicGrid<Level> = BasicGrid::new(&input);
+        let trails = Trails::new(&grid, Step::PlusOne);
+        assert_eq!(trails.total_score(), 36);
+        assert_eq!(trails.total_rating(), 81);
+
+        let start = Coord::new(0, 2);
+        assert_eq!(trails.score(start), 5);
+        assert_eq!(trails.rating(start), 20);
+        let all: Vec<Vec<Coord>> = trails.trails(start).collect();
+        assert_eq!(all.len(), 20);
+        assert!(all.windows(2).all(|w| w[0] != w[1]));
+        for t in all.iter() {
+            assert_eq!(t.len(), 10);
+            assert!(t.windows(2).all(|w| grid[w[1]].0 == grid[w[0]].0 + 1));
+            assert!(trails.peaks_from(start).contains(t.last().unwrap()));
+        }
+        assert_eq!(trails.nth_trail(start, 20), None);
+
+        let loose = Trails::new(&grid, Step::UpTo(2));
+        assert!(loose.total_rating() > 81);
+        assert!(Trails::new(&grid, Step::Ascent).total_rating() >= loose.total_rating());
+    }
+}
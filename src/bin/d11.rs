@@ -1,143 +1,324 @@
-use std::collections::HashMap;
-
-fn apply_part1_rules(stones: &mut Vec<u64>) {
-    let mut i = 0_usize;
-    while i < stones.len() {
-        let stone = stones[i];
-        if stone == 0 {
-            stones[i] = 1;
-        } else if stone.ilog10() % 2 == 1 {
-            let s = stone.to_string();
-            let (l, u): (u64, u64) = (
-                s[0..(s.len() / 2)].parse().unwrap(),
-                s[(s.len() / 2)..].parse().unwrap(),
-            );
-            stones[i] = u;
-            stones.insert(i, l);
-            i += 1;
-        } else {
-            stones[i] *= 2024;
+use std::{collections::HashMap, fmt::Display, ops::AddAssign, str::FromStr};
+
+use num_bigint::BigUint;
+
+/// When a rule applies, judged on the stone's value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Predicate {
+    Equals(u64),
+    /// the digit count is a multiple of this
+    DigitsMultipleOf(u32),
+    Always,
+}
+
+/// What a stone becomes when its rule applies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transform {
+    Set(u64),
+    /// cut the digits into this many parts, most significant first; all but
+    /// the first are equally wide and the first keeps any leftover digits
+    Split(u32),
+    Multiply(u64),
+    Add(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rule {
+    when: Predicate,
+    then: Transform,
+}
+
+/// An ordered rule list; each blink applies the first rule that matches.
+/// A stone no rule matches stays as it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rules {
+    /// base for counting and splitting digits
+    base: u64,
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    fn puzzle() -> Self {
+        use Predicate::*;
+        use Transform::*;
+        Self {
+            base: 10,
+            rules: vec![
+                Rule {
+                    when: Equals(0),
+                    then: Set(1),
+                },
+                Rule {
+                    when: DigitsMultipleOf(2),
+                    then: Split(2),
+                },
+                Rule {
+                    when: Always,
+                    then: Multiply(2024),
+                },
+            ],
         }
-        i += 1;
     }
-}
 
-fn part1(mut stones: Vec<u64>) {
-    println!("start:\t{:?}", stones);
-    for i in 0..25 {
-        apply_part1_rules(&mut stones);
-        if stones.len() < 25 {
-            println!("after:\t{:?}", stones)
+    fn digits(&self, stone: u64) -> u32 {
+        stone.checked_ilog(self.base).map_or(1, |l| l + 1)
+    }
+
+    fn matches(&self, when: Predicate, stone: u64) -> bool {
+        match when {
+            Predicate::Equals(v) => stone == v,
+            Predicate::DigitsMultipleOf(k) => self.digits(stone).is_multiple_of(k),
+            Predicate::Always => true,
         }
-        println!("after step {}, len is {}", i + 1, stones.len());
     }
-    println!("{}", stones.len());
-}
 
-enum OneOrTwo<T> {
-    One(T),
-    Two(T, T),
+    fn apply(&self, stone: u64) -> Vec<u64> {
+        let Some(rule) = self.rules.iter().find(|r| self.matches(r.when, stone)) else {
+            return vec![stone];
+        };
+        let overflow = || panic!("stone value {} overflows with {:?}", stone, rule.then);
+        match rule.then {
+            Transform::Set(v) => vec![v],
+            // the full width as a power of the base can overflow
+            Transform::Split(1) => vec![stone],
+            Transform::Split(k) => {
+                assert!(k > 0, "cannot split a stone into no parts");
+                let p = self.base.pow(self.digits(stone) / k);
+                let mut rest = stone;
+                let mut parts: Vec<u64> = (1..k)
+                    .map(|_| {
+                        let part = rest % p;
+                        rest /= p;
+                        part
+                    })
+                    .collect();
+                parts.push(rest);
+                parts.reverse();
+                parts
+            }
+            Transform::Multiply(m) => vec![stone.checked_mul(m).unwrap_or_else(overflow)],
+            Transform::Add(a) => vec![stone.checked_add(a).unwrap_or_else(overflow)],
+        }
+    }
 }
 
-struct OneOrTwoIter<T> {
-    data: OneOrTwo<T>,
-    reads: u8,
+#[derive(Debug)]
+enum RuleError {
+    MissingArrow(String),
+    UnknownPredicate(String),
+    UnknownTransform(String),
+    BadNumber {
+        line: String,
+        token: String,
+    },
+    BadCount {
+        line: String,
+        token: String,
+    },
+    BadBase(u64),
+    /// more parts than any stone has digits in the base
+    TooManyParts {
+        parts: u32,
+        digits: u32,
+    },
+    NoRules,
 }
 
-impl<T> OneOrTwoIter<T> {
-    fn new(data: OneOrTwo<T>) -> Self {
-        Self { data, reads: 0 }
+impl Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleError::MissingArrow(line) => write!(f, "no '=>' in \"{}\"", line),
+            RuleError::UnknownPredicate(p) => write!(f, "unknown predicate \"{}\"", p),
+            RuleError::UnknownTransform(t) => write!(f, "unknown transform \"{}\"", t),
+            RuleError::BadNumber { line, token } => {
+                write!(f, "bad number \"{}\" in \"{}\"", token, line)
+            }
+            RuleError::BadCount { line, token } => write!(
+                f,
+                "count \"{}\" in \"{}\" is not between 1 and {}",
+                token,
+                line,
+                u32::MAX
+            ),
+            RuleError::BadBase(b) => write!(f, "base {} is too small", b),
+            RuleError::TooManyParts { parts, digits } => write!(
+                f,
+                "cannot split into {} parts, stones have at most {} digits",
+                parts, digits
+            ),
+            RuleError::NoRules => write!(f, "no rules"),
+        }
     }
 }
-impl<T> Iterator for OneOrTwoIter<T>
-where
-    T: Copy,
-{
-    type Item = T;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.data {
-            OneOrTwo::One(v) => {
-                if self.reads > 0 {
-                    None
-                } else {
-                    self.reads += 1;
-                    Some(v)
+
+impl std::error::Error for RuleError {}
+
+/// One rule per line (or `;`-separated), `predicate => transform`, with an
+/// optional leading `base N`. Predicates are `value N`, `digits K` and
+/// `else`; transforms are `set N`, `split K`, `mul M` and `add A`.
+impl FromStr for Rules {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut base = 10;
+        let mut rules = vec![];
+        for line in s
+            .split([';', '\n'])
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+        {
+            let number = |token: &str| {
+                token
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| RuleError::BadNumber {
+                        line: line.to_string(),
+                        token: token.trim().to_string(),
+                    })
+            };
+            let count = |token: &str| {
+                u32::try_from(number(token)?)
+                    .ok()
+                    .filter(|k| *k > 0)
+                    .ok_or_else(|| RuleError::BadCount {
+                        line: line.to_string(),
+                        token: token.trim().to_string(),
+                    })
+            };
+            if let Some(b) = line.strip_prefix("base ") {
+                base = number(b)?;
+                if base < 2 {
+                    return Err(RuleError::BadBase(base));
                 }
+                continue;
             }
-            OneOrTwo::Two(v1, v2) => {
-                if self.reads == 0 {
-                    self.reads = 1;
-                    Some(v1)
-                } else if self.reads == 1 {
-                    self.reads = 2;
-                    Some(v2)
-                } else {
-                    None
+            let (when, then) = line
+                .split_once("=>")
+                .ok_or_else(|| RuleError::MissingArrow(line.to_string()))?;
+            let when = match when.split_whitespace().collect::<Vec<_>>()[..] {
+                ["value", n] => Predicate::Equals(number(n)?),
+                ["digits", k] => Predicate::DigitsMultipleOf(count(k)?),
+                ["else"] => Predicate::Always,
+                _ => return Err(RuleError::UnknownPredicate(when.trim().to_string())),
+            };
+            let then = match then.split_whitespace().collect::<Vec<_>>()[..] {
+                ["set", n] => Transform::Set(number(n)?),
+                ["split", k] => Transform::Split(count(k)?),
+                ["mul", m] => Transform::Multiply(number(m)?),
+                ["add", a] => Transform::Add(number(a)?),
+                _ => return Err(RuleError::UnknownTransform(then.trim().to_string())),
+            };
+            rules.push(Rule { when, then });
+        }
+        if rules.is_empty() {
+            return Err(RuleError::NoRules);
+        }
+        let rules = Rules { base, rules };
+        let digits = rules.digits(u64::MAX);
+        for rule in rules.rules.iter() {
+            if let Transform::Split(parts) = rule.then {
+                if parts > digits {
+                    return Err(RuleError::TooManyParts { parts, digits });
                 }
             }
         }
+        Ok(rules)
     }
 }
-impl<T> IntoIterator for OneOrTwo<T>
+
+/// A stone count. `u64` covers the puzzle, `u128` lasts to roughly 200
+/// blinks, and `BigUint` beyond that.
+trait Total: Clone + From<u8> + for<'a> AddAssign<&'a Self> {}
+
+impl<T> Total for T where T: Clone + From<u8> + for<'a> AddAssign<&'a T> {}
+
+struct Stones<N> {
+    rules: Rules,
+    memo: HashMap<(u64, usize), N>,
+}
+
+impl<N> Stones<N>
 where
-    T: Copy,
+    N: Total,
 {
-    type Item = T;
+    fn new(rules: Rules) -> Self {
+        Self {
+            rules,
+            memo: Default::default(),
+        }
+    }
 
-    type IntoIter = OneOrTwoIter<T>;
+    /// How many stones `stone` turns into after `blinks` blinks.
+    fn count(&mut self, stone: u64, blinks: usize) -> N {
+        if blinks == 0 {
+            return N::from(1);
+        }
+        if let Some(n) = self.memo.get(&(stone, blinks)) {
+            return n.clone();
+        }
+        let mut total = N::from(0);
+        for s in self.rules.apply(stone) {
+            total += &self.count(s, blinks - 1);
+        }
+        self.memo.insert((stone, blinks), total.clone());
+        total
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        OneOrTwoIter::new(self)
+    fn count_all(&mut self, stones: &[u64], blinks: usize) -> N {
+        let mut total = N::from(0);
+        for s in stones {
+            total += &self.count(*s, blinks);
+        }
+        total
     }
 }
-fn part2(starting_stones: Vec<u64>) {
-    use OneOrTwo::*;
-    let mut cache: HashMap<u64, OneOrTwo<_>> = Default::default();
-    let mut stones: HashMap<u64, u64> = starting_stones.into_iter().map(|v| (v, 1)).collect();
-    for step in 0..75 {
-        let mut new_stones: HashMap<u64, u64> = Default::default();
-        //println!("\n\n{:?}\n", stones);
-        stones.into_iter().for_each(|(stone_val, count)| {
-            let next = cache.entry(stone_val).or_insert_with_key(|val| {
-                if *val == 0 {
-                    One(1)
-                } else if val.ilog10() % 2 == 1 {
-                    let s = val.to_string();
-                    let (l, u): (u64, u64) = (
-                        s[0..(s.len() / 2)].parse().unwrap(),
-                        s[(s.len() / 2)..].parse().unwrap(),
-                    );
-                    Two(l, u)
-                } else {
-                    One(val * 2024)
-                }
-            });
-            match next {
-                One(v) => {
-                    *new_stones.entry(*v).or_default() += count;
-                }
-                Two(v1, v2) => {
-                    *new_stones.entry(*v1).or_default() += count;
-                    *new_stones.entry(*v2).or_default() += count;
-                }
+
+/// How many stones carry each value after `blinks` blinks.
+fn distribution<N>(rules: &Rules, stones: &[u64], blinks: usize) -> HashMap<u64, N>
+where
+    N: Total,
+{
+    let mut counts: HashMap<u64, N> = Default::default();
+    for s in stones {
+        *counts.entry(*s).or_insert_with(|| N::from(0)) += &N::from(1);
+    }
+    for _ in 0..blinks {
+        let mut next: HashMap<u64, N> = Default::default();
+        for (stone, n) in counts.iter() {
+            for s in rules.apply(*stone) {
+                *next.entry(s).or_insert_with(|| N::from(0)) += n;
             }
-        });
-        stones = new_stones;
-        let r: u64 = stones.values().sum();
-        println!("at step {}: {}", step + 1, r);
+        }
+        counts = next;
     }
+    counts
 }
 
 fn main() {
     //let input = TEST;
     let input = std::fs::read_to_string("input/d11.txt").unwrap();
-    let mut stones: Vec<u64> = input
+    let stones: Vec<u64> = input
         .split_ascii_whitespace()
         .map(str::parse)
         .collect::<Result<_, _>>()
         .unwrap();
-    part2(stones);
+    let rules = Rules::puzzle();
+    //let rules: Rules = "value 0 => set 1; digits 3 => split 3; else => mul 2025".parse().unwrap();
+    let mut counter: Stones<u64> = Stones::new(rules.clone());
+    println!("{}", counter.count_all(&stones, 25));
+    println!("{}", counter.count_all(&stones, 75));
+
+    let mut big: Stones<BigUint> = Stones::new(rules.clone());
+    println!("{}", big.count_all(&stones, 500));
+
+    let dist: HashMap<u64, u64> = distribution(&rules, &stones, 75);
+    let (value, n) = dist.iter().max_by_key(|(v, n)| (**n, **v)).unwrap();
+    println!(
+        "{} distinct values after 75 blinks, most common {} x{}",
+        dist.len(),
+        value,
+        n
+    );
 }
 
 //static TEST: &str = "0 1 10 99 999";
@@ -145,6 +326,7 @@ static TEST: &str = "125 17";
 
 #[cfg(test)]
 mod test {
+    use super::*;
 
     #[test]
     fn test_log() {
@@ -155,4 +337,88 @@ mod test {
         assert_eq!(101_u64.ilog10(), 2);
         assert_eq!(1003_u64.ilog10(), 3);
     }
+
+    #[test]
+    fn test_count() {
+        let stones: Vec<u64> = TEST.split(' ').map(|s| s.parse().unwrap()).collect();
+        let rules = Rules::puzzle();
+        assert_eq!(rules.apply(253000), vec![253, 0]);
+        assert_eq!(rules.apply(1000), vec![10, 0]);
+        assert_eq!(rules.apply(0), vec![1]);
+        assert_eq!(rules.apply(101), vec![204424]);
+
+        let mut counter: Stones<u64> = Stones::new(rules.clone());
+        assert_eq!(counter.count_all(&stones, 6), 22);
+        assert_eq!(counter.count_all(&stones, 25), 55312);
+
+        let dist: HashMap<u64, u64> = distribution(&rules, &stones, 6);
+        assert_eq!(dist.values().sum::<u64>(), 22);
+        assert_eq!(dist[&2], 4);
+
+        let mut big: Stones<BigUint> = Stones::new(rules.clone());
+        let total = big.count_all(&stones, 300);
+        assert!(total.bits() > 128);
+        let dist: HashMap<u64, BigUint> = distribution(&rules, &stones, 300);
+        assert_eq!(dist.values().sum::<BigUint>(), total);
+    }
+
+    #[test]
+    fn test_rules() {
+        let puzzle = "value 0 => set 1
+digits 2 => split 2
+else => mul 2024";
+        assert_eq!(puzzle.parse::<Rules>().unwrap(), Rules::puzzle());
+
+        let rules: Rules = "base 2; value 0 => add 1; digits 3 => split 3; else => mul 3"
+            .parse()
+            .unwrap();
+        // 0b110011 has six binary digits
+        assert_eq!(rules.apply(0b110011), vec![0b11, 0b00, 0b11]);
+        assert_eq!(rules.apply(0b11), vec![0b1001]);
+        assert_eq!(rules.apply(0), vec![1]);
+
+        assert!(matches!(
+            "value 0 -> set 1".parse::<Rules>(),
+            Err(RuleError::MissingArrow(_))
+        ));
+        assert!(matches!(
+            "odd => set 1".parse::<Rules>(),
+            Err(RuleError::UnknownPredicate(_))
+        ));
+        assert!(matches!("".parse::<Rules>(), Err(RuleError::NoRules)));
+        for bad in [
+            "digits 0 => split 2",
+            "digits 2 => split 0",
+            "digits 4294967298 => split 2",
+            "else => split 4294967296",
+        ] {
+            assert!(
+                matches!(bad.parse::<Rules>(), Err(RuleError::BadCount { .. })),
+                "{}",
+                bad
+            );
+        }
+
+        // unguarded splits keep the leftover digits in the first part
+        let rules: Rules = "else => split 2".parse().unwrap();
+        assert_eq!(rules.apply(12345), vec![123, 45]);
+        assert_eq!(rules.apply(7), vec![7, 0]);
+        let rules: Rules = "else => split 3".parse().unwrap();
+        assert_eq!(rules.apply(1234567), vec![123, 45, 67]);
+        let rules: Rules = "else => split 1".parse().unwrap();
+        assert_eq!(rules.apply(u64::MAX), vec![u64::MAX]);
+
+        assert!("else => split 20".parse::<Rules>().is_ok());
+        assert!(matches!(
+            "else => split 21".parse::<Rules>(),
+            Err(RuleError::TooManyParts {
+                parts: 21,
+                digits: 20
+            })
+        ));
+        assert!(matches!(
+            "else => split 21; base 2".parse::<Rules>(),
+            Ok(Rules { base: 2, .. })
+        ));
+    }
 }
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
 
 use utils::{AsciiByte, BasicGrid, Coord, Dir};
 
@@ -63,6 +66,180 @@ impl Region {
     fn sides(&self, grid: &BasicGrid<AsciiByte>) -> usize {
         self.plots.iter().map(|c| corners(*c, grid)).sum()
     }
+
+    /// Unit fence edges, keyed by the corner they start from. Each runs with
+    /// the region on its right.
+    fn fence_edges(&self) -> HashMap<Corner, Vec<Dir>> {
+        let mut edges: HashMap<Corner, Vec<Dir>> = Default::default();
+        for p in self.plots.iter() {
+            for dir in Dir::ALL {
+                if p.in_dir(dir).is_some_and(|n| self.plots.contains(&n)) {
+                    continue;
+                }
+                let (r, c) = (p.row, p.col);
+                let (from, heading) = match dir {
+                    Dir::Up => ((r, c), Dir::Right),
+                    Dir::Right => ((r, c + 1), Dir::Down),
+                    Dir::Down => ((r + 1, c + 1), Dir::Left),
+                    Dir::Left => ((r + 1, c), Dir::Up),
+                };
+                edges.entry(from).or_default().push(heading);
+            }
+        }
+        edges
+    }
+
+    fn boundary(&self) -> Boundary {
+        let mut edges = self.fence_edges();
+        let mut loops: Vec<Vec<Corner>> = vec![];
+        // the top-left corner of a loop is always a turn, so it's a vertex
+        while let Some(start) = edges.keys().min().copied() {
+            let mut path: Vec<Corner> = vec![];
+            let mut cur = start;
+            let mut heading: Option<Dir> = None;
+            loop {
+                let outs = edges.get_mut(&cur).unwrap();
+                // where two of the region's plots touch only diagonally,
+                // turning left crosses to the other plot and stays on the
+                // same loop, so a hole pinched against another fence is
+                // still traced on its own
+                let i = heading
+                    .and_then(|h| {
+                        [h.turn_left(), h, h.turn_right()]
+                            .into_iter()
+                            .find_map(|d| outs.iter().position(|o| *o == d))
+                    })
+                    .unwrap_or(0);
+                let next = outs.swap_remove(i);
+                if outs.is_empty() {
+                    edges.remove(&cur);
+                }
+                if heading != Some(next) {
+                    path.push(cur);
+                }
+                heading = Some(next);
+                cur = step(cur, next);
+                if cur == start {
+                    break;
+                }
+            }
+            loops.push(path);
+        }
+        // the outer boundary is the only clockwise loop, and the largest
+        loops.sort_by_key(|l| std::cmp::Reverse(signed_area(l)));
+        let mut loops = loops.into_iter();
+        Boundary {
+            outer: loops.next().unwrap_or_default(),
+            holes: loops.collect(),
+        }
+    }
+}
+
+/// A point on the fence lattice: the top-left corner of plot (row, col).
+type Corner = (usize, usize);
+
+fn step((r, c): Corner, dir: Dir) -> Corner {
+    match dir {
+        Dir::Up => (r - 1, c),
+        Dir::Down => (r + 1, c),
+        Dir::Left => (r, c - 1),
+        Dir::Right => (r, c + 1),
+    }
+}
+
+/// Twice the area enclosed by a loop, positive when it runs clockwise on
+/// screen.
+fn signed_area(path: &[Corner]) -> i64 {
+    let n = path.len();
+    (0..n)
+        .map(|i| {
+            let ((r1, c1), (r2, c2)) = (path[i], path[(i + 1) % n]);
+            c1 as i64 * r2 as i64 - c2 as i64 * r1 as i64
+        })
+        .sum()
+}
+
+/// A region's fences as closed polylines through the corners where they
+/// turn, so every vertex starts a new side. The outer boundary runs
+/// clockwise and holes anticlockwise, keeping the region on the right.
+#[derive(Debug)]
+struct Boundary {
+    outer: Vec<Corner>,
+    holes: Vec<Vec<Corner>>,
+}
+
+impl Boundary {
+    fn loops(&self) -> impl Iterator<Item = &Vec<Corner>> {
+        std::iter::once(&self.outer).chain(self.holes.iter())
+    }
+
+    fn sides(&self) -> usize {
+        self.loops().map(Vec::len).sum()
+    }
+}
+
+const SVG_SCALE: usize = 20;
+
+/// Draws every region filled in its own colour with its fences on top, and
+/// a dot at each vertex so sides can be counted by eye.
+fn write_svg<W>(grid: &BasicGrid<AsciiByte>, regions: &[Region], mut w: W) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let (width, height) = (grid.width * SVG_SCALE, grid.height * SVG_SCALE);
+    writeln!(
+        &mut w,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"-4 -4 {} {}\">",
+        width + 8,
+        height + 8,
+        width + 8,
+        height + 8
+    )?;
+    writeln!(
+        &mut w,
+        "<style>text {{ font: {}px monospace; text-anchor: middle; dominant-baseline: central; }}</style>",
+        SVG_SCALE / 2
+    )?;
+    let boundaries: Vec<Boundary> = regions.iter().map(Region::boundary).collect();
+    for (i, (r, b)) in regions.iter().zip(boundaries.iter()).enumerate() {
+        let d: Vec<String> = b
+            .loops()
+            .map(|l| {
+                let points: Vec<String> = l
+                    .iter()
+                    .map(|(row, col)| format!("{} {}", col * SVG_SCALE, row * SVG_SCALE))
+                    .collect();
+                format!("M {} Z", points.join(" L "))
+            })
+            .collect();
+        writeln!(
+            &mut w,
+            "<path d=\"{}\" fill=\"hsl({:.0}, 60%, 75%)\" fill-rule=\"evenodd\" stroke=\"black\" stroke-width=\"2\"><title>{}: area {}, sides {}</title></path>",
+            d.join(" "),
+            (i as f64 * 137.508) % 360.0,
+            r.code,
+            r.area(),
+            b.sides()
+        )?;
+        for p in r.plots.iter() {
+            writeln!(
+                &mut w,
+                "<text x=\"{}\" y=\"{}\">{}</text>",
+                p.col * SVG_SCALE + SVG_SCALE / 2,
+                p.row * SVG_SCALE + SVG_SCALE / 2,
+                r.code
+            )?;
+        }
+    }
+    for (row, col) in boundaries.iter().flat_map(|b| b.loops().flatten()) {
+        writeln!(
+            &mut w,
+            "<circle cx=\"{}\" cy=\"{}\" r=\"2.5\" fill=\"red\"/>",
+            col * SVG_SCALE,
+            row * SVG_SCALE
+        )?;
+    }
+    writeln!(&mut w, "</svg>")
 }
 
 fn corners(c: Coord, grid: &BasicGrid<AsciiByte>) -> usize {
@@ -118,6 +295,140 @@ fn build_regions(grid: &BasicGrid<AsciiByte>) -> Vec<Region> {
     regions
 }
 
+/// Which regions touch, how much fence they share, and which ones sit
+/// inside another.
+struct RegionGraph {
+    regions: Vec<Region>,
+    /// index of the region owning each plot
+    owner: BasicGrid<usize>,
+    /// shared fence length per touching pair, smaller index first
+    fences: HashMap<(usize, usize), usize>,
+    /// the region directly around each enclosed region
+    encloser: Vec<Option<usize>>,
+}
+
+impl RegionGraph {
+    fn new(grid: &BasicGrid<AsciiByte>) -> Self {
+        let regions = build_regions(grid);
+        let mut owner: BasicGrid<usize> = BasicGrid::from_fn(grid.width, grid.height, |_| 0);
+        for (i, r) in regions.iter().enumerate() {
+            for p in r.plots.iter() {
+                owner[*p] = i;
+            }
+        }
+
+        let mut fences: HashMap<(usize, usize), usize> = Default::default();
+        for p in
+            (0..grid.height).flat_map(|row| (0..grid.width).map(move |col| Coord::new(row, col)))
+        {
+            for dir in [Dir::Right, Dir::Down] {
+                if let Some(n) = grid.next_pos(p, dir) {
+                    let (a, b) = (owner[p], owner[n]);
+                    if a != b {
+                        *fences.entry((a.min(b), a.max(b))).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        // a region encloses whatever fills its holes, so only the holes
+        // need flooding rather than the whole map once per region
+        let mut enclosers: Vec<Vec<usize>> = vec![vec![]; regions.len()];
+        for (i, r) in regions.iter().enumerate() {
+            let seeds: Vec<Coord> = r.boundary().holes.iter().map(|h| inside(h)).collect();
+            for j in flood(&owner, i, seeds) {
+                enclosers[j].push(i);
+            }
+        }
+        // the innermost encloser is the one enclosed by all the others
+        let encloser = enclosers
+            .iter()
+            .map(|es| es.iter().copied().max_by_key(|e| enclosers[*e].len()))
+            .collect();
+
+        Self {
+            regions,
+            owner,
+            fences,
+            encloser,
+        }
+    }
+
+    fn region_at(&self, c: Coord) -> usize {
+        self.owner[c]
+    }
+
+    /// Regions touching region `i`, with the length of fence shared.
+    fn neighbours(&self, i: usize) -> Vec<(usize, usize)> {
+        let mut out: Vec<(usize, usize)> = self
+            .fences
+            .iter()
+            .filter_map(|((a, b), len)| match (*a == i, *b == i) {
+                (true, _) => Some((*b, *len)),
+                (_, true) => Some((*a, *len)),
+                _ => None,
+            })
+            .collect();
+        out.sort();
+        out
+    }
+
+    fn encloser(&self, i: usize) -> Option<usize> {
+        self.encloser[i]
+    }
+
+    /// Regions sitting directly inside region `i`.
+    fn enclosed_by(&self, i: usize) -> Vec<usize> {
+        (0..self.regions.len())
+            .filter(|j| self.encloser[*j] == Some(i))
+            .collect()
+    }
+
+    /// Fence between any region of code `a` and any region of code `b`.
+    fn shared_fence(&self, a: AsciiByte, b: AsciiByte) -> usize {
+        self.fences
+            .iter()
+            .filter(|((i, j), _)| {
+                let (ci, cj) = (self.regions[*i].code, self.regions[*j].code);
+                (ci, cj) == (a, b) || (ci, cj) == (b, a)
+            })
+            .map(|(_, len)| len)
+            .sum()
+    }
+}
+
+/// A plot just inside a hole, on the left of the loop's first side.
+fn inside(hole: &[Corner]) -> Coord {
+    let ((r0, c0), (r1, c1)) = (hole[0], hole[1]);
+    let (row, col) = match (r1.cmp(&r0), c1.cmp(&c0)) {
+        (_, std::cmp::Ordering::Greater) => (r0 - 1, c0),
+        (_, std::cmp::Ordering::Less) => (r0, c0 - 1),
+        (std::cmp::Ordering::Greater, _) => (r0, c0),
+        _ => (r0 - 1, c0 - 1),
+    };
+    Coord::new(row, col)
+}
+
+/// Regions other than `wall` reachable from `seeds` without crossing it.
+fn flood(owner: &BasicGrid<usize>, wall: usize, seeds: Vec<Coord>) -> Vec<usize> {
+    let mut seen: HashSet<Coord> = seeds.iter().copied().collect();
+    let mut stack = seeds;
+    let mut found: Vec<usize> = vec![];
+    while let Some(c) = stack.pop() {
+        found.push(owner[c]);
+        for dir in Dir::ALL {
+            if let Some(n) = owner.next_pos(c, dir) {
+                if owner[n] != wall && seen.insert(n) {
+                    stack.push(n);
+                }
+            }
+        }
+    }
+    found.sort();
+    found.dedup();
+    found
+}
+
 fn part1() {
     //let input = TEST2;
     let input = std::fs::read_to_string("input/d12.txt").unwrap();
@@ -161,8 +472,40 @@ fn part2() {
         regions.iter().map(|r| r.part2_price(&grid)).sum::<usize>()
     );
 }
+fn draw(input: &str, path: &str) {
+    let data: Vec<&str> = input.lines().collect();
+    let grid: BasicGrid<AsciiByte> = BasicGrid::new(&data);
+    let regions = build_regions(&grid);
+    write_svg(&grid, &regions, std::fs::File::create(path).unwrap()).unwrap();
+}
+
+fn enclaves() {
+    //let input = TEST1;
+    let input = std::fs::read_to_string("input/d12.txt").unwrap();
+    let data: Vec<&str> = input.lines().collect();
+    let grid: BasicGrid<AsciiByte> = BasicGrid::new(&data);
+    let graph = RegionGraph::new(&grid);
+    for (i, r) in graph.regions.iter().enumerate() {
+        let inside = graph.enclosed_by(i);
+        if !inside.is_empty() {
+            let codes: Vec<String> = inside
+                .iter()
+                .map(|j| graph.regions[*j].code.to_string())
+                .collect();
+            println!(
+                "Region {} ({} neighbours) encloses {}",
+                r.code,
+                graph.neighbours(i).len(),
+                codes.join(", ")
+            );
+        }
+    }
+}
+
 fn main() {
     part2();
+    //enclaves();
+    //draw(TEST4.trim(), "d12.svg");
 }
 
 static TEST: &str = r#"
@@ -200,3 +543,116 @@ AAABBA
 ABBAAA
 ABBAAA
 AAAAAA";
+
+static PINCHED: &str = "BBBBBB
+BAAABB
+BAXABB
+BAABAB
+BBBBBB";
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use super::*;
+
+    fn regions_of(input: &str) -> (BasicGrid<AsciiByte>, Vec<Region>) {
+        let data: Vec<&str> = input.trim().lines().collect();
+        let grid: BasicGrid<AsciiByte> = BasicGrid::new(&data);
+        let regions = build_regions(&grid);
+        (grid, regions)
+    }
+
+    #[test]
+    fn test_boundary() {
+        for input in [TEST, TEST1, TEST2, TEST3, TEST4] {
+            let (grid, regions) = regions_of(input);
+            for r in regions.iter() {
+                assert_eq!(r.boundary().sides(), r.sides(&grid), "region {}", r.code);
+            }
+        }
+
+        let (_, regions) = regions_of(TEST1);
+        let o = regions.iter().find(|r| r.code == AsciiByte(b'O')).unwrap();
+        let b = o.boundary();
+        assert_eq!(b.outer, vec![(0, 0), (0, 5), (5, 5), (5, 0)]);
+        assert_eq!(b.holes.len(), 4);
+        assert!(b.holes.iter().all(|h| h.len() == 4 && signed_area(h) == -2));
+
+        // the two B regions touch diagonally, but each is its own hole in A
+        let (_, regions) = regions_of(TEST4);
+        let a = regions.iter().find(|r| r.code == AsciiByte(b'A')).unwrap();
+        assert_eq!(a.boundary().holes.len(), 2);
+        assert!(a.boundary().holes.iter().all(|h| h.len() == 4));
+        assert_eq!(a.boundary().sides(), 12);
+
+        // A's hole around X touches the outside at a corner
+        let (grid, regions) = regions_of(PINCHED);
+        let a = regions
+            .iter()
+            .find(|r| r.contains(Coord::new(1, 1)))
+            .unwrap();
+        let b = a.boundary();
+        assert_eq!(
+            b.outer,
+            vec![(1, 1), (1, 4), (3, 4), (3, 3), (4, 3), (4, 1)]
+        );
+        assert_eq!(b.holes, vec![vec![(2, 2), (3, 2), (3, 3), (2, 3)]]);
+        assert_eq!(b.sides(), a.sides(&grid));
+
+        let (grid, regions) = regions_of(TEST3);
+        let mut svg: Vec<u8> = vec![];
+        write_svg(&grid, &regions, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert_eq!(svg.matches("<path").count(), regions.len());
+        assert!(svg.contains("E: area 17, sides 12"));
+    }
+
+    #[test]
+    fn test_graph() {
+        let data: Vec<&str> = TEST1.lines().collect();
+        let graph = RegionGraph::new(&BasicGrid::new(&data));
+        let o = graph.region_at(Coord::new(0, 0));
+        let x = graph.region_at(Coord::new(1, 1));
+        assert_eq!(graph.enclosed_by(o).len(), 4);
+        assert_eq!(graph.encloser(x), Some(o));
+        assert_eq!(graph.neighbours(x), vec![(o, 4)]);
+        assert_eq!(graph.shared_fence(AsciiByte(b'X'), AsciiByte(b'O')), 16);
+
+        // every fence is either shared or on the map edge
+        let data: Vec<&str> = TEST2.lines().collect();
+        let graph = RegionGraph::new(&BasicGrid::new(&data));
+        let perimeters: usize = graph.regions.iter().map(Region::perieter).sum();
+        let shared: usize = graph.fences.values().sum();
+        assert_eq!(perimeters, 2 * shared + 4 * 10);
+
+        let nested = "AAAAA
+ABBBA
+ABCBA
+ABBBA
+AAAAA";
+        let data: Vec<&str> = nested.lines().collect();
+        let graph = RegionGraph::new(&BasicGrid::new(&data));
+        let (a, b, c) = (0..3)
+            .map(|i| graph.region_at(Coord::new(i, i)))
+            .collect_tuple()
+            .unwrap();
+        assert_eq!(graph.encloser(c), Some(b));
+        assert_eq!(graph.encloser(b), Some(a));
+        assert_eq!(graph.encloser(a), None);
+        assert_eq!(graph.enclosed_by(a), vec![b]);
+        assert_eq!(graph.shared_fence(AsciiByte(b'A'), AsciiByte(b'C')), 0);
+
+        // A's hole around X meets the outside at a corner, yet it's still
+        // a hole and X is enclosed by A alone
+        let data: Vec<&str> = PINCHED.lines().collect();
+        let graph = RegionGraph::new(&BasicGrid::new(&data));
+        let (a, x) = (
+            graph.region_at(Coord::new(1, 1)),
+            graph.region_at(Coord::new(2, 2)),
+        );
+        assert_eq!(graph.regions[a].boundary().holes.len(), 1);
+        assert_eq!(graph.encloser(x), Some(a));
+        assert_eq!(graph.enclosed_by(a), vec![x]);
+    }
+}
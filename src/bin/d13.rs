@@ -1,16 +1,15 @@
-use num_rational::{Ratio, Rational64};
 use regex::Regex;
-use std::str::FromStr;
+use std::{fmt::Display, str::FromStr};
 
 #[derive(Debug)]
 struct Part1Game {
-    a: (u64, u64),
-    b: (u64, u64),
-    total: (u64, u64),
+    a: (i64, i64),
+    b: (i64, i64),
+    total: (i64, i64),
 }
 
 impl Part1Game {
-    fn new(a_0: u64, a_1: u64, b_0: u64, b_1: u64, prize_0: u64, prize_1: u64) -> Self {
+    fn new(a_0: i64, a_1: i64, b_0: i64, b_1: i64, prize_0: i64, prize_1: i64) -> Self {
         Part1Game {
             a: (a_0, a_1),
             b: (b_0, b_1),
@@ -38,74 +37,220 @@ impl FromStr for Part1Game {
     }
 }
 
-trait CloseEnough {
-    fn close_enough(self, other: Self) -> bool;
+const A_COST: i128 = 3;
+const B_COST: i128 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Presses {
+    a: i128,
+    b: i128,
+}
+
+impl Presses {
+    fn cost(&self) -> i128 {
+        A_COST * self.a + B_COST * self.b
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Solution {
+    /// no whole, non-negative number of presses reaches the prize
+    Impossible,
+    /// exactly one non-negative combination fits
+    Unique(Presses),
+    /// the buttons are parallel and `count` non-negative combinations line
+    /// up with the prize; `cheapest` is the one to pay for
+    Several { cheapest: Presses, count: i128 },
+    /// a button that doesn't move can be pressed any number of times; this
+    /// is the cheapest combination
+    Infinite(Presses),
+}
+
+impl Solution {
+    fn cost(&self) -> Option<i128> {
+        match self {
+            Solution::Impossible => None,
+            Solution::Unique(p) | Solution::Several { cheapest: p, .. } | Solution::Infinite(p) => {
+                Some(p.cost())
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Overflow;
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "arithmetic overflow")
+    }
 }
 
-impl CloseEnough for f64 {
-    fn close_enough(self, other: Self) -> bool {
-        (self - other).abs() < 0.0001
+impl std::error::Error for Overflow {}
+
+fn mul(a: i128, b: i128) -> Result<i128, Overflow> {
+    a.checked_mul(b).ok_or(Overflow)
+}
+
+fn sub(a: i128, b: i128) -> Result<i128, Overflow> {
+    a.checked_sub(b).ok_or(Overflow)
+}
+
+fn add(a: i128, b: i128) -> Result<i128, Overflow> {
+    a.checked_add(b).ok_or(Overflow)
+}
+
+/// `(g, x, y)` with `a * x + b * y == g == gcd(a, b) >= 0`.
+fn ext_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a.abs(), a.signum(), 0)
+    } else {
+        let (g, x, y) = ext_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
     }
 }
 
-impl CloseEnough for Ratio<i64> {
-    fn close_enough(self, other: Self) -> bool {
-        self == other
+fn floor_div(a: i128, b: i128) -> i128 {
+    let d = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        d - 1
+    } else {
+        d
     }
 }
-fn play_game(game: Part1Game) -> Option<u64> {
-    let (a_x, a_y) = game.a;
-    let (a_x, a_y): (Ratio<i64>, Ratio<i64>) = (
-        Ratio::from_integer(a_x.try_into().unwrap()),
-        Ratio::from_integer(a_y.try_into().unwrap()),
-    );
-    let (b_x, b_y) = game.b;
-    let (b_x, b_y): (Ratio<i64>, Ratio<i64>) = (
-        Ratio::from_integer(b_x.try_into().unwrap()),
-        Ratio::from_integer(b_y.try_into().unwrap()),
-    );
-    let (total_x, total_y) = game.total;
-    let (total_x, total_y): (Ratio<i64>, Ratio<i64>) = (
-        Ratio::from_integer(total_x.try_into().unwrap()),
-        Ratio::from_integer(total_y.try_into().unwrap()),
-    );
 
-    let R = a_x / a_y;
-    let nb = (total_x - (R * total_y)) / (b_x - (R * b_y));
-    let na = (total_y - nb * b_y) / a_y;
-    if na.close_enough(na.round()) && nb.close_enough(nb.round()) {
-        //println!("OK: {:?}", game);
-        if !(na.is_integer() && nb.is_integer()) {
-            panic!()
+fn ceil_div(a: i128, b: i128) -> i128 {
+    -floor_div(-a, b)
+}
+
+/// Cheapest non-negative `a, b` with `u * a + v * b == w`, classified by
+/// how many such pairs there are.
+fn solve_line(u: i128, v: i128, w: i128) -> Result<Solution, Overflow> {
+    if u == 0 && v == 0 {
+        return Ok(if w == 0 {
+            Solution::Infinite(Presses { a: 0, b: 0 })
+        } else {
+            Solution::Impossible
+        });
+    }
+    let (g, x, y) = ext_gcd(u, v);
+    if w % g != 0 {
+        return Ok(Solution::Impossible);
+    }
+    // every solution is (a0 + k * p, b0 - k * q)
+    let (a0, b0) = (mul(x, w / g)?, mul(y, w / g)?);
+    let (p, q) = (v / g, u / g);
+    let (mut lo, mut hi): (Option<i128>, Option<i128>) = (None, None);
+    let mut bound = |k: i128, lower: bool| {
+        if lower {
+            lo = Some(lo.map_or(k, |l| l.max(k)));
         } else {
-            let na: u64 = na.to_integer().try_into().unwrap();
-            let nb: u64 = nb.to_integer().try_into().unwrap();
-            Some(3_u64 * na + nb)
+            hi = Some(hi.map_or(k, |h| h.min(k)));
         }
+    };
+    // a >= 0
+    match p.signum() {
+        1 => bound(ceil_div(-a0, p), true),
+        -1 => bound(floor_div(-a0, p), false),
+        _ if a0 < 0 => return Ok(Solution::Impossible),
+        _ => {}
+    }
+    // b >= 0
+    match q.signum() {
+        1 => bound(floor_div(b0, q), false),
+        -1 => bound(ceil_div(b0, q), true),
+        _ if b0 < 0 => return Ok(Solution::Impossible),
+        _ => {}
+    }
+    if let (Some(l), Some(h)) = (lo, hi) {
+        if l > h {
+            return Ok(Solution::Impossible);
+        }
+    }
+    // cost is linear in k, so the cheapest end of the range wins; with
+    // positive costs it can't fall forever, so that end exists
+    let slope = sub(mul(A_COST, p)?, mul(B_COST, q)?)?;
+    let k = match (slope > 0, lo, hi) {
+        (true, Some(l), _) | (false, _, Some(l)) => l,
+        (_, l, h) => l.or(h).unwrap_or(0),
+    };
+    let cheapest = Presses {
+        a: add(a0, mul(k, p)?)?,
+        b: sub(b0, mul(k, q)?)?,
+    };
+    Ok(match (lo, hi) {
+        (Some(l), Some(h)) if l == h => Solution::Unique(cheapest),
+        (Some(l), Some(h)) => Solution::Several {
+            cheapest,
+            count: add(sub(h, l)?, 1)?,
+        },
+        _ => Solution::Infinite(cheapest),
+    })
+}
+
+fn play_game(game: &Part1Game) -> Result<Solution, Overflow> {
+    let (a_x, a_y) = (game.a.0 as i128, game.a.1 as i128);
+    let (b_x, b_y) = (game.b.0 as i128, game.b.1 as i128);
+    let (p_x, p_y) = (game.total.0 as i128, game.total.1 as i128);
+
+    let det = sub(mul(a_x, b_y)?, mul(a_y, b_x)?)?;
+    if det != 0 {
+        // Cramer's rule
+        let na = sub(mul(p_x, b_y)?, mul(p_y, b_x)?)?;
+        let nb = sub(mul(a_x, p_y)?, mul(a_y, p_x)?)?;
+        if na % det != 0 || nb % det != 0 {
+            return Ok(Solution::Impossible);
+        }
+        let (a, b) = (na / det, nb / det);
+        return Ok(if a >= 0 && b >= 0 {
+            Solution::Unique(Presses { a, b })
+        } else {
+            Solution::Impossible
+        });
+    }
+
+    // both buttons move along one line through the origin (or not at all),
+    // so the prize has to be on it too, and then one axis says everything
+    let (d_x, d_y) = if (a_x, a_y) != (0, 0) {
+        (a_x, a_y)
     } else {
-        //println!("No (na: {}, nb: {}) {:?}", na, nb, game);
-        None
+        (b_x, b_y)
+    };
+    if (d_x, d_y) == (0, 0) {
+        return Ok(if (p_x, p_y) == (0, 0) {
+            Solution::Infinite(Presses { a: 0, b: 0 })
+        } else {
+            Solution::Impossible
+        });
+    }
+    if sub(mul(d_x, p_y)?, mul(d_y, p_x)?)? != 0 {
+        return Ok(Solution::Impossible);
+    }
+    if d_x != 0 {
+        solve_line(a_x, b_x, p_x)
+    } else {
+        solve_line(a_y, b_y, p_y)
     }
 }
 
-fn part1(part1: Part1Game) -> Option<u64> {
-    play_game(part1)
+fn part1(part1: Part1Game) -> Result<Solution, Overflow> {
+    play_game(&part1)
 }
 
-fn part2(mut part2: Part1Game) -> Option<u64> {
+fn part2(mut part2: Part1Game) -> Result<Solution, Overflow> {
     let factor = 10000000000000;
-    part2.total.0 += factor;
-    part2.total.1 += factor;
-    play_game(part2)
+    part2.total.0 = part2.total.0.checked_add(factor).ok_or(Overflow)?;
+    part2.total.1 = part2.total.1.checked_add(factor).ok_or(Overflow)?;
+    play_game(&part2)
 }
 
 fn main() {
     //let input = TEST;
     let input = std::fs::read_to_string("input/d13.txt").unwrap();
-    let sum: u64 = input
+    let sum: i128 = input
         .trim()
         .split("\n\n")
-        .map(|s| part2(s.parse().unwrap()).unwrap_or(0))
+        .map(|s| part2(s.parse().unwrap()).unwrap().cost().unwrap_or(0))
         .sum();
     println!("{sum}");
 }
@@ -127,3 +272,114 @@ Button A: X+69, Y+23
 Button B: X+27, Y+71
 Prize: X=18641, Y=10279
 "#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn game(a: (i64, i64), b: (i64, i64), total: (i64, i64)) -> Part1Game {
+        Part1Game { a, b, total }
+    }
+
+    #[test]
+    fn test_games() {
+        let games: Vec<&str> = TEST.trim().split("\n\n").collect();
+        let costs: Vec<Option<i128>> = games
+            .iter()
+            .map(|s| part1(s.parse().unwrap()).unwrap().cost())
+            .collect();
+        assert_eq!(costs, vec![Some(280), None, Some(200), None]);
+        let part2_total: i128 = games
+            .iter()
+            .filter_map(|s| part2(s.parse().unwrap()).unwrap().cost())
+            .sum();
+        assert_eq!(part2_total, 875318608908);
+    }
+
+    #[test]
+    fn test_degenerate() {
+        use Solution::*;
+        let solve = |a, b, total| play_game(&game(a, b, total)).unwrap();
+        // (0, 4), (3, 2) and (6, 0)
+        assert_eq!(
+            solve((2, 4), (3, 6), (12, 24)),
+            Several {
+                cheapest: Presses { a: 0, b: 4 },
+                count: 3
+            }
+        );
+        // (0, 8), (1, 4) and (2, 0)
+        assert_eq!(
+            solve((4, 4), (1, 1), (8, 8)),
+            Several {
+                cheapest: Presses { a: 2, b: 0 },
+                count: 3
+            }
+        );
+        assert_eq!(
+            solve((4, 8), (6, 12), (10, 20)),
+            Unique(Presses { a: 1, b: 1 })
+        );
+        assert_eq!(solve((2, 4), (3, 6), (12, 25)), Impossible);
+        assert_eq!(solve((2, 4), (4, 8), (3, 6)), Impossible);
+        assert_eq!(
+            solve((0, 0), (5, 5), (10, 10)),
+            Infinite(Presses { a: 0, b: 2 })
+        );
+        assert_eq!(
+            solve((0, 0), (0, 0), (0, 0)),
+            Infinite(Presses { a: 0, b: 0 })
+        );
+        assert_eq!(solve((0, 0), (0, 0), (1, 0)), Impossible);
+        assert_eq!(solve((0, 3), (0, 5), (0, 7)), Impossible);
+        assert_eq!(
+            solve((0, 3), (0, 5), (0, 11)),
+            Unique(Presses { a: 2, b: 1 })
+        );
+        assert_eq!(
+            solve((5, 5), (0, 0), (10, 10)),
+            Infinite(Presses { a: 2, b: 0 })
+        );
+        assert_eq!(
+            solve((1, 0), (0, 1), (5, 7)),
+            Unique(Presses { a: 5, b: 7 })
+        );
+
+        let huge = game((i64::MAX, 1), (1, i64::MAX), (i64::MAX, i64::MAX));
+        assert_eq!(part2(huge), Err(Overflow));
+    }
+
+    #[test]
+    fn test_machine() {
+        // every game is also a two-button, two-axis linalg::Machine
+        use utils::linalg::Machine;
+        let machine = |g: &Part1Game| {
+            Machine::new(
+                vec![vec![g.a.0, g.a.1], vec![g.b.0, g.b.1]],
+                vec![A_COST as i64, B_COST as i64],
+                vec![g.total.0, g.total.1],
+            )
+        };
+        let mut games: Vec<Part1Game> = TEST
+            .trim()
+            .split("\n\n")
+            .map(|s| s.parse().unwrap())
+            .collect();
+        games.extend([
+            game((2, 4), (3, 6), (12, 24)),
+            game((4, 8), (6, 12), (10, 20)),
+            game((2, 4), (4, 8), (3, 6)),
+            game((0, 0), (5, 5), (10, 10)),
+            game((0, 3), (0, 5), (0, 11)),
+        ]);
+        for g in games.iter() {
+            let plan = machine(g).cheapest();
+            assert_eq!(
+                plan.map(|p| p.cost as i128),
+                play_game(g).unwrap().cost(),
+                "{:?}",
+                g
+            );
+        }
+    }
+}
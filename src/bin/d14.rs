@@ -1,9 +1,10 @@
 use raylib::prelude::*;
 use rayon::prelude::*;
 
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, io::Write, ops::Range, str::FromStr};
 
 use regex::Regex;
+use utils::BitGrid;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct Coord {
@@ -16,7 +17,7 @@ impl Coord {
         Coord { x, y }
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Bot {
     name: String,
     p: Coord,
@@ -31,6 +32,36 @@ impl Bot {
             name: format!("Bot p({}, {}), v({}, {})", p_x, p_y, v_x, v_y),
         }
     }
+
+    /// Position after `t` seconds, without stepping through them.
+    fn at(&self, t: usize, board: Board) -> Coord {
+        let wrap = |p: i16, v: i16, size: i16| {
+            let t = (t % size as usize) as i64;
+            (p as i64 + v as i64 * t).rem_euclid(size as i64) as i16
+        };
+        Coord::new(
+            wrap(self.p.x, self.v.x, board.width),
+            wrap(self.p.y, self.v.y, board.height),
+        )
+    }
+}
+
+/// Dimensions of the room; bots wrap around both edges.
+#[derive(Clone, Copy, Debug)]
+struct Board {
+    width: i16,
+    height: i16,
+}
+
+impl Board {
+    const PUZZLE: Board = Board {
+        width: 101,
+        height: 103,
+    };
+    const TEST: Board = Board {
+        width: 11,
+        height: 7,
+    };
 }
 
 impl FromStr for Bot {
@@ -48,24 +79,33 @@ impl FromStr for Bot {
     }
 }
 
-fn part1() {
-    let input = std::fs::read_to_string("input/d14.txt").unwrap();
-    //let input = TEST;
-    let (width, height) = (101_i16, 103_i16);
-    let mut bots: Vec<Bot> = input
+fn parse(input: &str) -> Vec<Bot> {
+    input
         .lines()
         .map(str::trim)
         .map(|s| s.parse().unwrap())
-        .collect();
+        .collect()
+}
 
-    for _ in 0..100 {
-        bots.iter_mut().for_each(|b| {
-            b.p.x = (b.p.x + b.v.x).rem_euclid(width);
-            b.p.y = (b.p.y + b.v.y).rem_euclid(height);
-        });
-    }
+/// The bots as they stand after `t` seconds.
+fn bots_at(bots: &[Bot], t: usize, board: Board) -> Vec<Bot> {
+    bots.iter()
+        .map(|b| Bot {
+            p: b.at(t, board),
+            ..b.clone()
+        })
+        .collect()
+}
+
+fn part1(bots: &[Bot], board: Board) -> usize {
+    safety_factor(&bots_at(bots, 100, board), board)
+}
+
+/// Product of the bot counts in each quadrant, ignoring the middle row and
+/// column.
+fn safety_factor(bots: &[Bot], board: Board) -> usize {
     let mut map: HashMap<(bool, bool), usize> = Default::default();
-    let (half_w, half_h) = (width / 2, height / 2);
+    let (half_w, half_h) = (board.width / 2, board.height / 2);
     //println!("half_w: {half_w}\thalf_h: {half_h}");
     for bot in bots.iter() {
         let (x, y) = (bot.p.x, bot.p.y);
@@ -93,13 +133,13 @@ fn part1() {
             *map.entry(q).or_default() += 1;
         }
     }
-    println!("{}", map.values().fold(1, |acc, v| acc * v));
+    map.values().product()
 }
 
-fn move_bots(bots: &mut Vec<Bot>, width: i16, height: i16) {
+fn move_bots(bots: &mut Vec<Bot>, board: Board) {
     bots.par_iter_mut().for_each(|b| {
-        b.p.x = (b.p.x + b.v.x).rem_euclid(width);
-        b.p.y = (b.p.y + b.v.y).rem_euclid(height);
+        b.p.x = (b.p.x + b.v.x).rem_euclid(board.width);
+        b.p.y = (b.p.y + b.v.y).rem_euclid(board.height);
     });
 }
 
@@ -108,7 +148,226 @@ fn dump_bot_positions(bots: &Vec<Bot>) {
         println!("bot {} is at {:?}", bot.name, bot.p);
     });
 }
-fn part2() {
+/// Spatial statistics for one frame. A picture shows up as a collapse in
+/// variance and a jump in cluster size and run length.
+#[derive(Debug, Clone, Copy)]
+struct FrameStats {
+    x_variance: f64,
+    y_variance: f64,
+    safety_factor: usize,
+    largest_cluster: usize,
+    longest_run: usize,
+}
+
+impl FrameStats {
+    fn new(bots: &[Bot], board: Board) -> Self {
+        let grid = occupancy(bots, board);
+        Self {
+            x_variance: variance(bots.iter().map(|b| b.p.x)),
+            y_variance: variance(bots.iter().map(|b| b.p.y)),
+            safety_factor: safety_factor(bots, board),
+            largest_cluster: largest_cluster(&grid),
+            longest_run: longest_run(&grid),
+        }
+    }
+
+    /// Whether this frame is far more clumped than `baseline`, as a picture
+    /// would be.
+    fn stands_out(&self, baseline: &FrameStats) -> bool {
+        self.largest_cluster > 2 * baseline.largest_cluster
+            && self.longest_run > baseline.longest_run
+    }
+}
+
+fn variance(values: impl Iterator<Item = i16>) -> f64 {
+    let values: Vec<f64> = values.map(f64::from).collect();
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / n
+}
+
+fn occupancy(bots: &[Bot], board: Board) -> BitGrid {
+    let mut grid = BitGrid::new(board.width as usize, board.height as usize);
+    for b in bots {
+        grid.set(utils::Coord::new(b.p.y as usize, b.p.x as usize));
+    }
+    grid
+}
+
+/// Size of the largest 4-connected group of occupied cells.
+fn largest_cluster(grid: &BitGrid) -> usize {
+    let mut seen = BitGrid::new(grid.width, grid.height);
+    let mut best = 0;
+    for start in grid.iter() {
+        if !seen.set(start) {
+            continue;
+        }
+        let mut size = 0;
+        let mut todo = vec![start];
+        while let Some(c) = todo.pop() {
+            size += 1;
+            let (r, col) = (c.row, c.col);
+            let neighbours = [
+                (r > 0).then(|| utils::Coord::new(r - 1, col)),
+                (r + 1 < grid.height).then(|| utils::Coord::new(r + 1, col)),
+                (col > 0).then(|| utils::Coord::new(r, col - 1)),
+                (col + 1 < grid.width).then(|| utils::Coord::new(r, col + 1)),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if grid.test(n) && seen.set(n) {
+                    todo.push(n);
+                }
+            }
+        }
+        best = best.max(size);
+    }
+    best
+}
+
+/// Longest horizontal run of occupied cells in any row.
+fn longest_run(grid: &BitGrid) -> usize {
+    let mut best = 0;
+    for row in 0..grid.height {
+        let mut run = 0;
+        for col in 0..grid.width {
+            if grid.test(utils::Coord::new(row, col)) {
+                run += 1;
+                best = best.max(run);
+            } else {
+                run = 0;
+            }
+        }
+    }
+    best
+}
+
+fn render(grid: &BitGrid) -> String {
+    let mut out = String::with_capacity((grid.width + 1) * grid.height);
+    for row in 0..grid.height {
+        for col in 0..grid.width {
+            out.push(if grid.test(utils::Coord::new(row, col)) {
+                '#'
+            } else {
+                '.'
+            });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Smallest `t >= 0` with `t = a (mod m)` and `t = b (mod n)`, if any.
+fn crt(a: i64, m: i64, b: i64, n: i64) -> Option<i64> {
+    // extended Euclid: g = gcd(m, n) = m * x + n * y
+    let (mut g, mut g1, mut x, mut x1) = (m, n, 1_i64, 0_i64);
+    while g1 != 0 {
+        let q = g / g1;
+        (g, g1) = (g1, g - q * g1);
+        (x, x1) = (x1, x - q * x1);
+    }
+    if (b - a) % g != 0 {
+        return None;
+    }
+    let lcm = m / g * n;
+    let k = ((b - a) / g * x).rem_euclid(n / g);
+    Some((a + m * k).rem_euclid(lcm))
+}
+
+/// Tightest frames per axis combined into candidates by `find_anomaly`.
+const CANDIDATES: usize = 3;
+
+/// Frame where the bots bunch up into a picture. x positions repeat every
+/// `width` frames and y positions every `height`, so the variance of each
+/// axis only has to be scored over one period. The few tightest frames per
+/// axis are combined with CRT, and the candidate whose cluster and row run
+/// stand out the most against the loosest frame wins. `None` if none of
+/// them stand out.
+fn find_anomaly(bots: &[Bot], board: Board) -> Option<usize> {
+    // frames of one period, tightest first
+    let by_variance = |period: i16, axis: fn(Coord) -> i16| {
+        let mut frames: Vec<(f64, usize)> = (0..period as usize)
+            .map(|t| (variance(bots.iter().map(|b| axis(b.at(t, board)))), t))
+            .collect();
+        frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+        frames.into_iter().map(|(_, t)| t).collect::<Vec<usize>>()
+    };
+    let xs = by_variance(board.width, |c| c.x);
+    let ys = by_variance(board.height, |c| c.y);
+    let combine = |tx: usize, ty: usize| {
+        crt(
+            tx as i64,
+            board.width as i64,
+            ty as i64,
+            board.height as i64,
+        )
+        .map(|t| t as usize)
+    };
+    let stats = |t: usize| FrameStats::new(&bots_at(bots, t, board), board);
+
+    let baseline = stats(combine(*xs.last()?, *ys.last()?)?);
+    xs.iter()
+        .take(CANDIDATES)
+        .flat_map(|tx| ys.iter().take(CANDIDATES).map(move |ty| (*tx, *ty)))
+        .filter_map(|(tx, ty)| combine(tx, ty))
+        .map(|t| (stats(t), t))
+        .filter(|(s, _)| s.stands_out(&baseline))
+        .max_by_key(|(s, _)| {
+            (
+                s.largest_cluster,
+                s.longest_run,
+                std::cmp::Reverse(s.safety_factor),
+            )
+        })
+        .map(|(_, t)| t)
+}
+
+fn headless(bots: &[Bot], board: Board) {
+    let Some(frame) = find_anomaly(bots, board) else {
+        println!("no frame stands out");
+        return;
+    };
+    let bots = bots_at(bots, frame, board);
+    let stats = FrameStats::new(&bots, board);
+    println!("{frame}");
+    println!(
+        "x variance {:.1}, y variance {:.1}, safety factor {}, largest cluster {}, longest run {}",
+        stats.x_variance,
+        stats.y_variance,
+        stats.safety_factor,
+        stats.largest_cluster,
+        stats.longest_run
+    );
+    print!("{}", render(&occupancy(&bots, board)));
+}
+
+/// Writes one CSV row of `FrameStats` per frame in `frames`, for plotting.
+fn write_metrics<W: Write>(
+    bots: &[Bot],
+    board: Board,
+    frames: Range<usize>,
+    w: &mut W,
+) -> std::io::Result<()> {
+    writeln!(
+        w,
+        "frame,safety_factor,x_variance,y_variance,largest_cluster,longest_run"
+    )?;
+    for t in frames {
+        let stats = FrameStats::new(&bots_at(bots, t, board), board);
+        writeln!(
+            w,
+            "{},{},{:.3},{:.3},{},{}",
+            t,
+            stats.safety_factor,
+            stats.x_variance,
+            stats.y_variance,
+            stats.largest_cluster,
+            stats.longest_run
+        )?;
+    }
+    Ok(())
+}
+
+fn part2(mut bots: Vec<Bot>, board: Board) {
     let (mut rl, thread) = raylib::init().size(1000, 1000).build();
     rl.set_target_fps(90);
 
@@ -119,26 +378,18 @@ fn part2() {
     let mut last_update: f64 = 0.0;
     let mut update_freq: f64 = 0.001;
 
-    let input = std::fs::read_to_string("input/d14.txt").unwrap();
-    //let input = TEST;
-    let (width, height) = (101_i16, 103_i16);
-    //let (width, height) = (11_i16, 7_i16);
     let mut frame = 0;
-    let mut bots: Vec<Bot> = input
-        .lines()
-        .map(str::trim)
-        .map(|s| s.parse().unwrap())
-        .collect();
+    let anomaly = find_anomaly(&bots, board);
 
     while !rl.window_should_close() {
         let current_time = rl.get_time();
         if last_update + update_freq < current_time {
-            move_bots(&mut bots, width, height);
+            move_bots(&mut bots, board);
             //println!("FRAME {frame}\n");
             //dump_bot_positions(&bots);
             last_update = current_time;
             frame += 1;
-            if frame == 8150 {
+            if Some(frame) == anomaly {
                 update_freq = 1.0;
             }
         }
@@ -159,7 +410,15 @@ fn part2() {
 }
 
 fn main() {
-    part2();
+    let input = std::fs::read_to_string("input/d14.txt").unwrap();
+    let board = Board::PUZZLE;
+    //let (input, board) = (TEST.to_string(), Board::TEST);
+    let bots = parse(&input);
+    println!("{}", part1(&bots, board));
+    headless(&bots, board);
+    //let frames = 0..board.width as usize * board.height as usize;
+    //write_metrics(&bots, board, frames, &mut std::fs::File::create("d14.csv").unwrap()).unwrap();
+    //part2(bots, board);
 }
 
 static TEST: &str = "p=0,4 v=3,-3
@@ -174,3 +433,78 @@ p=9,3 v=2,3
 p=7,3 v=-1,2
 p=2,4 v=2,-3
 p=9,5 v=-3,-3";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let bots = parse(TEST);
+        assert_eq!(part1(&bots, Board::TEST), 12);
+        let mut stepped = bots.clone();
+        for t in 0..=100 {
+            assert!(bots
+                .iter()
+                .zip(&stepped)
+                .all(|(b, s)| b.at(t, Board::TEST) == s.p));
+            move_bots(&mut stepped, Board::TEST);
+        }
+
+        let mut csv = Vec::new();
+        write_metrics(&bots, Board::TEST, 100..103, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.lines().nth(1).unwrap().starts_with("100,12,"));
+
+        assert_eq!(crt(2, 3, 3, 5), Some(8));
+        assert_eq!(crt(1, 4, 2, 6), None);
+        assert_eq!(crt(1, 4, 3, 6), Some(9));
+
+        // a vertical bar of three, plus a lone bot
+        let bots = vec![
+            Bot::new(1, 0, 0, 0),
+            Bot::new(1, 1, 0, 0),
+            Bot::new(1, 2, 0, 0),
+            Bot::new(3, 2, 0, 0),
+        ];
+        let board = Board {
+            width: 5,
+            height: 3,
+        };
+        let grid = occupancy(&bots, board);
+        assert_eq!(largest_cluster(&grid), 3);
+        assert_eq!(longest_run(&grid), 1);
+        assert_eq!(render(&grid), ".#...\n.#...\n.#.#.\n");
+    }
+
+    #[test]
+    fn test_anomaly() {
+        // sixty bots that fill a 10x6 block at frame 700 and scatter
+        // otherwise
+        let board = Board {
+            width: 31,
+            height: 37,
+        };
+        let t = 700;
+        let bots: Vec<Bot> = (0..60_i16)
+            .map(|i| {
+                let (x, y) = (10 + i % 10, 15 + i / 10);
+                let (vx, vy) = (i * 7 % 13 - 6, i * 5 % 11 - 5);
+                let back = |p: i16, v: i16, size: i16| {
+                    (p as i64 - v as i64 * t).rem_euclid(size as i64) as i16
+                };
+                Bot::new(back(x, vx, board.width), back(y, vy, board.height), vx, vy)
+            })
+            .collect();
+        assert_eq!(find_anomaly(&bots, board), Some(t as usize));
+        let picture = FrameStats::new(&bots_at(&bots, t as usize, board), board);
+        assert_eq!((picture.largest_cluster, picture.longest_run), (60, 10));
+
+        // nothing moves, so no frame is any different
+        let still: Vec<Bot> = (0..60)
+            .map(|i| Bot::new(i % 31, i * 7 % 37, 0, 0))
+            .collect();
+        assert_eq!(find_anomaly(&still, board), None);
+    }
+}
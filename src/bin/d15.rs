@@ -1,46 +1,24 @@
-use std::{fmt::Display};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{Read, Write},
+    process::{Command, Stdio},
+};
 
-use utils::{BasicGrid, Coord, Dir};
+use utils::{AsciiByte, BasicGrid, Coord, Dir, GridCell};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, GridCell)]
 enum State {
+    #[cell('#')]
     Wall,
+    #[cell('O')]
     Box,
+    #[cell('.')]
     Empty,
+    #[cell('@')]
     Robot,
 }
 
-impl Display for State {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                State::Wall => "#",
-                State::Box => "O",
-                State::Empty => ".",
-                State::Robot => "@",
-            }
-        )
-    }
-}
-
-impl From<u8> for State {
-    fn from(value: u8) -> Self {
-        if value == b'#' {
-            State::Wall
-        } else if value == b'.' {
-            State::Empty
-        } else if value == b'@' {
-            State::Robot
-        } else if value == b'O' {
-            State::Box
-        } else {
-            panic!();
-        }
-    }
-}
-
 #[derive(Clone, Copy, Debug)]
 struct Dir15(Dir);
 
@@ -67,6 +45,57 @@ struct MoveState {
     dir: Dir,
 }
 
+/// A cell one instruction changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Change<S> {
+    pos: Coord,
+    before: S,
+    after: S,
+}
+
+/// Lends out the grid for one instruction, noting the contents of each
+/// cell the first time it is swapped.
+struct Recorder<'a, S> {
+    grid: &'a mut BasicGrid<S>,
+    changes: Vec<Change<S>>,
+}
+
+impl<'a, S: Copy + PartialEq> Recorder<'a, S> {
+    fn new(grid: &'a mut BasicGrid<S>) -> Self {
+        Self {
+            grid,
+            changes: vec![],
+        }
+    }
+
+    fn swap(&mut self, a: Coord, b: Coord) {
+        for pos in [a, b] {
+            if !self.changes.iter().any(|c| c.pos == pos) {
+                let s = self.grid[pos];
+                self.changes.push(Change {
+                    pos,
+                    before: s,
+                    after: s,
+                });
+            }
+        }
+        self.grid.swap(a, b);
+    }
+
+    /// The cells whose contents actually differ now.
+    fn finish(self) -> Vec<Change<S>> {
+        let grid = self.grid;
+        self.changes
+            .into_iter()
+            .map(|c| Change {
+                after: grid[c.pos],
+                ..c
+            })
+            .filter(|c| c.before != c.after)
+            .collect()
+    }
+}
+
 fn check_movable(grid: &BasicGrid<State>, start: Coord, dir: Dir) -> Option<MoveState> {
     if let Some(c) = grid.next_pos(start, dir) {
         match *grid.at(c) {
@@ -107,34 +136,60 @@ fn check_movable(grid: &BasicGrid<State>, start: Coord, dir: Dir) -> Option<Move
     }
 }
 
-fn move_items(grid: &mut BasicGrid<State>, move_state: MoveState) -> Coord {
+fn move_items(rec: &mut Recorder<State>, move_state: MoveState) -> Coord {
     let mut to = move_state.end;
     let back_dir = move_state.dir.turn_right().turn_right();
-    let mut from = grid.next_pos(to, back_dir).unwrap();
+    let mut from = rec.grid.next_pos(to, back_dir).unwrap();
     loop {
-        grid.swap(to, from);
+        rec.swap(to, from);
         if from == move_state.start {
             break;
         }
 
         to = from;
-        from = grid.next_pos(from, back_dir).unwrap();
+        from = rec.grid.next_pos(from, back_dir).unwrap();
     }
     to
 }
-fn apply_dir(grid: &mut BasicGrid<State>, mut robot_pos: Coord, dir: Dir15) -> Coord {
-    if let Some(move_state) = check_movable(grid, robot_pos, dir.0) {
-        robot_pos = move_items(grid, move_state);
+
+/// What a recorded `Run` needs from either warehouse.
+trait Warehouse: Copy + PartialEq + Display + GridCell + TryFrom<u8> {
+    const ROBOT: Self;
+
+    /// Moves the robot at `robot` one cell towards `dir`, pushing whatever
+    /// is in the way, and returns where it ends up.
+    fn push(rec: &mut Recorder<Self>, robot: Coord, dir: Dir) -> Coord;
+
+    /// Whether this cell is where a box's GPS coordinate is measured.
+    fn is_box(self) -> bool;
+}
+
+impl Warehouse for State {
+    const ROBOT: Self = State::Robot;
+
+    fn push(rec: &mut Recorder<Self>, robot: Coord, dir: Dir) -> Coord {
+        match check_movable(rec.grid, robot, dir) {
+            Some(move_state) => move_items(rec, move_state),
+            None => robot,
+        }
+    }
+
+    fn is_box(self) -> bool {
+        matches!(self, State::Box)
     }
-    robot_pos
 }
 
-fn part1() {
-    //let input = TEST1;
-    let input = std::fs::read_to_string("input/d15.txt").unwrap();
+fn gps<S: Warehouse>(grid: &BasicGrid<S>) -> usize {
+    grid.find_with(|s| s.is_box())
+        .iter()
+        .map(|c| c.row * 100 + c.col)
+        .sum()
+}
+
+fn parse(input: &str) -> (BasicGrid<State>, Vec<Dir15>) {
     let p = input.find("\n\n").unwrap();
     let grid_input: Vec<&str> = input[0..p].trim().split("\n").collect();
-    let mut grid: BasicGrid<State> = BasicGrid::new(&grid_input);
+    let grid: BasicGrid<State> = BasicGrid::new(&grid_input);
     let directions: Vec<Dir15> = input[p..]
         .as_bytes()
         .iter()
@@ -146,53 +201,54 @@ fn part1() {
             }
         })
         .collect();
-    let mut robot_pos = grid
-        .find_with(|s| matches!(*s, State::Robot))
-        .pop()
-        .unwrap();
+    (grid, directions)
+}
 
+fn part1() {
+    //let input = TEST1;
+    let input = std::fs::read_to_string("input/d15.txt").unwrap();
+    let (grid, directions) = parse(&input);
+    let mut run = Run::new(grid);
     for dir in directions {
-        robot_pos = apply_dir(&mut grid, robot_pos, dir);
+        run.apply(dir.0);
         //println!("after applying {dir:?}:");
-        //grid.display_all();
+        //run.grid.display_all();
     }
-    let res: usize = grid
-        .find_with(|state| matches!(*state, State::Box))
-        .iter()
-        .map(|c| c.row * 100 + c.col)
-        .sum();
-    println!("{}", res);
+    println!("{}", gps(&run.grid));
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, GridCell)]
 enum StatePart2 {
+    #[cell('#')]
     Wall,
+    #[cell(']')]
     BoxR,
+    #[cell('[')]
     BoxL,
-    #[default]
+    #[cell('.', default)]
     Empty,
+    #[cell('@')]
     Robot,
 }
 
-impl StatePart2 {
-    fn as_str(&self) -> &str {
-        match self {
-            StatePart2::Wall => "#",
-            StatePart2::BoxR => "]",
-            StatePart2::BoxL => "[",
-            StatePart2::Empty => ".",
-            StatePart2::Robot => "@",
+fn dump(grid: &BasicGrid<StatePart2>) {
+    for y in 0..(grid.height) {
+        for x in 0..(grid.width) {
+            print!("{}", grid[Coord::new(y, x)]);
         }
+        println!();
     }
 }
 
-fn dump(grid: &BasicGrid<StatePart2>) {
+fn render<S: Display>(grid: &BasicGrid<S>) -> String {
+    let mut out = String::new();
     for y in 0..(grid.height) {
         for x in 0..(grid.width) {
-            print!("{}", grid[Coord::new(y, x)].as_str());
+            out += &grid[Coord::new(y, x)].to_string();
         }
-        println!();
+        out.push('\n');
     }
+    out
 }
 
 fn check_movable_part2(grid: &BasicGrid<StatePart2>, pos: Coord, dir: Dir) -> bool {
@@ -229,106 +285,551 @@ fn check_movable_part2(grid: &BasicGrid<StatePart2>, pos: Coord, dir: Dir) -> bo
     }
 }
 
-fn move_items_part2(grid: &mut BasicGrid<StatePart2>, start: Coord, dir: Dir) -> Coord {
-    if let Some(c) = grid.next_pos(start, dir) {
-        match (*grid.at(c), dir) {
+fn move_items_part2(rec: &mut Recorder<StatePart2>, start: Coord, dir: Dir) -> Coord {
+    if let Some(c) = rec.grid.next_pos(start, dir) {
+        match (*rec.grid.at(c), dir) {
             (StatePart2::Wall, _) => panic!(),
             (StatePart2::Robot, _) => panic!(),
             (StatePart2::Empty, _) => {
-                grid.swap(start, c);
+                rec.swap(start, c);
             }
             (StatePart2::BoxL | StatePart2::BoxR, Dir::Left | Dir::Right) => {
-                move_items_part2(grid, c, dir);
-                grid.swap(start, c);
+                move_items_part2(rec, c, dir);
+                rec.swap(start, c);
             }
             (StatePart2::BoxR, _) => {
-                move_items_part2(grid, c, dir);
-                move_items_part2(grid, grid.next_pos(c, Dir::Left).unwrap(), dir);
-                grid.swap(start, c);
+                move_items_part2(rec, c, dir);
+                move_items_part2(rec, rec.grid.next_pos(c, Dir::Left).unwrap(), dir);
+                rec.swap(start, c);
             }
             (StatePart2::BoxL, _) => {
-                move_items_part2(grid, c, dir);
-                move_items_part2(grid, grid.next_pos(c, Dir::Right).unwrap(), dir);
-                grid.swap(start, c);
+                move_items_part2(rec, c, dir);
+                move_items_part2(rec, rec.grid.next_pos(c, Dir::Right).unwrap(), dir);
+                rec.swap(start, c);
             }
         }
         return c;
     }
     panic!()
 }
-fn apply_dir_part2(grid: &mut BasicGrid<StatePart2>, mut robot_pos: Coord, dir: Dir) -> Coord {
-    if check_movable_part2(grid, robot_pos, dir) {
-        robot_pos = move_items_part2(grid, robot_pos, dir);
+
+impl Warehouse for StatePart2 {
+    const ROBOT: Self = StatePart2::Robot;
+
+    fn push(rec: &mut Recorder<Self>, robot: Coord, dir: Dir) -> Coord {
+        if check_movable_part2(rec.grid, robot, dir) {
+            move_items_part2(rec, robot, dir)
+        } else {
+            robot
+        }
+    }
+
+    fn is_box(self) -> bool {
+        matches!(self, StatePart2::BoxL)
     }
-    robot_pos
+}
+
+/// The part 2 warehouse, with everything but the robot twice as wide.
+fn widen(base_grid: &BasicGrid<State>) -> BasicGrid<StatePart2> {
+    BasicGrid::from_fn(base_grid.width * 2, base_grid.height, |c| {
+        let left = c.col % 2 == 0;
+        match base_grid[Coord::new(c.row, c.col / 2)] {
+            State::Wall => StatePart2::Wall,
+            State::Box if left => StatePart2::BoxL,
+            State::Box => StatePart2::BoxR,
+            State::Empty => StatePart2::Empty,
+            State::Robot if left => StatePart2::Robot,
+            State::Robot => StatePart2::Empty,
+        }
+    })
 }
 
 fn part2() {
     //let input = TEST1;
     let input = std::fs::read_to_string("input/d15.txt").unwrap();
-    let p = input.find("\n\n").unwrap();
-    let grid_input: Vec<&str> = input[0..p].trim().split("\n").collect();
-    let base_grid: BasicGrid<State> = BasicGrid::new(&grid_input);
-    let directions: Vec<Dir15> = input[p..]
-        .as_bytes()
-        .iter()
-        .filter_map(|&c| {
-            if c.is_ascii_whitespace() {
-                None
-            } else {
-                Some(c.into())
-            }
-        })
-        .collect();
+    let (base_grid, directions) = parse(&input);
+    let grid = widen(&base_grid);
 
-    let mut grid: BasicGrid<StatePart2> =
-        BasicGrid::new_default(base_grid.width * 2, base_grid.height);
+    dump(&grid);
 
-    for r in 0..base_grid.height {
-        for c in 0..base_grid.width {
-            match base_grid[Coord::new(r, c)] {
-                State::Wall => {
-                    grid[Coord::new(r, 2 * c)] = StatePart2::Wall;
-                    grid[Coord::new(r, 2 * c + 1)] = StatePart2::Wall;
-                }
-                State::Box => {
-                    grid[Coord::new(r, 2 * c)] = StatePart2::BoxL;
-                    grid[Coord::new(r, 2 * c + 1)] = StatePart2::BoxR;
+    let mut run = Run::new(grid);
+    for dir in directions {
+        run.apply(dir.0);
+        //println!("after applying {dir:?}:");
+        //dump(&run.grid);
+    }
+    //run.write_replay(&mut std::fs::File::create("d15.replay").unwrap()).unwrap();
+    println!("{}", gps(&run.grid));
+}
+
+/// What an instruction did, as written in replay files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    /// the robot stayed put
+    Blocked,
+    /// the robot stepped into free space
+    Moved,
+    /// the robot pushed at least one box
+    Pushed,
+}
+
+impl Outcome {
+    fn as_byte(self) -> u8 {
+        match self {
+            Outcome::Blocked => b'b',
+            Outcome::Moved => b'm',
+            Outcome::Pushed => b'p',
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            b'b' => Some(Outcome::Blocked),
+            b'm' => Some(Outcome::Moved),
+            b'p' => Some(Outcome::Pushed),
+            _ => None,
+        }
+    }
+}
+
+/// One executed instruction and the cells it changed.
+#[derive(Clone, Debug)]
+struct Step<S> {
+    dir: Dir,
+    from: Coord,
+    to: Coord,
+    changes: Vec<Change<S>>,
+}
+
+impl<S> Step<S> {
+    fn outcome(&self) -> Outcome {
+        if self.from == self.to {
+            Outcome::Blocked
+        } else if self.changes.len() > 2 {
+            Outcome::Pushed
+        } else {
+            Outcome::Moved
+        }
+    }
+}
+
+/// A warehouse that keeps every instruction it executed so it can be
+/// rewound. `steps[..cursor]` are applied to `grid`; the rest can be redone.
+struct Run<S> {
+    grid: BasicGrid<S>,
+    robot: Coord,
+    steps: Vec<Step<S>>,
+    cursor: usize,
+}
+
+impl<S: Warehouse> Run<S> {
+    fn new(grid: BasicGrid<S>) -> Self {
+        let robot = grid.find_with(|s| *s == S::ROBOT).pop().unwrap();
+        Self {
+            grid,
+            robot,
+            steps: vec![],
+            cursor: 0,
+        }
+    }
+
+    /// Executes `dir`, forgetting anything that could have been redone.
+    fn apply(&mut self, dir: Dir) -> &Step<S> {
+        self.steps.truncate(self.cursor);
+        let mut rec = Recorder::new(&mut self.grid);
+        let to = S::push(&mut rec, self.robot, dir);
+        self.steps.push(Step {
+            dir,
+            from: self.robot,
+            to,
+            changes: rec.finish(),
+        });
+        self.robot = to;
+        self.cursor += 1;
+        &self.steps[self.cursor - 1]
+    }
+
+    fn undo(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        let step = &self.steps[self.cursor];
+        for c in step.changes.iter() {
+            self.grid[c.pos] = c.before;
+        }
+        self.robot = step.from;
+        true
+    }
+
+    fn redo(&mut self) -> bool {
+        if self.cursor == self.steps.len() {
+            return false;
+        }
+        let step = &self.steps[self.cursor];
+        for c in step.changes.iter() {
+            self.grid[c.pos] = c.after;
+        }
+        self.robot = step.to;
+        self.cursor += 1;
+        true
+    }
+
+    /// Rewinds or replays to the state after the first `n` instructions.
+    fn seek(&mut self, n: usize) {
+        assert!(n <= self.steps.len(), "only {} steps", self.steps.len());
+        while self.cursor > n {
+            self.undo();
+        }
+        while self.cursor < n {
+            self.redo();
+        }
+    }
+
+    /// Cells that differ between the states after `a` and after `b`
+    /// instructions, with `before` taken from `a`. `None` if either is past
+    /// the recorded steps.
+    fn diff(&self, a: usize, b: usize) -> Option<Vec<Change<S>>> {
+        let (lo, hi) = (a.min(b), a.max(b));
+        let mut cells: HashMap<Coord, Change<S>> = HashMap::new();
+        for c in self
+            .steps
+            .get(lo..hi)?
+            .iter()
+            .flat_map(|s| s.changes.iter())
+        {
+            cells
+                .entry(c.pos)
+                .and_modify(|d| d.after = c.after)
+                .or_insert(*c);
+        }
+        let mut cells: Vec<Change<S>> = cells
+            .into_values()
+            .filter(|c| c.before != c.after)
+            .map(|c| {
+                if a > b {
+                    Change {
+                        before: c.after,
+                        after: c.before,
+                        ..c
+                    }
+                } else {
+                    c
                 }
-                State::Empty => {
-                    grid[Coord::new(r, 2 * c)] = StatePart2::Empty;
-                    grid[Coord::new(r, 2 * c + 1)] = StatePart2::Empty;
+            })
+            .collect();
+        cells.sort_by_key(|c| (c.pos.row, c.pos.col));
+        Some(cells)
+    }
+
+    /// The warehouse before any instruction.
+    fn initial(&self) -> BasicGrid<S> {
+        let mut grid = self.grid.clone();
+        for c in self.steps[..self.cursor]
+            .iter()
+            .rev()
+            .flat_map(|s| s.changes.iter())
+        {
+            grid[c.pos] = c.before;
+        }
+        grid
+    }
+
+    /// Writes the initial warehouse, a blank line, the instructions, a blank
+    /// line and one outcome letter per instruction (`b`locked, `m`oved,
+    /// `p`ushed). Lines are wrapped at `REPLAY_WIDTH`.
+    fn write_replay<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        write!(w, "{}", render(&self.initial()))?;
+        let moves: Vec<u8> = self.steps.iter().map(|s| s.dir.as_ascii_byte().0).collect();
+        let outcomes: Vec<u8> = self.steps.iter().map(|s| s.outcome().as_byte()).collect();
+        for section in [moves, outcomes] {
+            writeln!(w)?;
+            for line in section.chunks(REPLAY_WIDTH) {
+                w.write_all(line)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds a run from `write_replay` output, checking every recorded
+    /// outcome as it goes. The run ends after the last instruction.
+    fn read_replay(s: &str) -> Result<Self, ReplayError> {
+        let s = s.replace("\r\n", "\n");
+        let sections: Vec<&str> = s.trim().split("\n\n").collect();
+        let [grid, moves, outcomes] = sections[..] else {
+            return Err(ReplayError::Sections(sections.len()));
+        };
+        let lines: Vec<&str> = grid.lines().collect();
+        let grid: BasicGrid<S> =
+            BasicGrid::try_new(&lines).map_err(|_| ReplayError::BadGrid(grid.to_string()))?;
+        if grid.count_with(|c| **c == S::ROBOT) != 1 {
+            return Err(ReplayError::BadGrid(lines.join("\n")));
+        }
+        let bytes = |section: &str| -> Vec<u8> {
+            section
+                .bytes()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect()
+        };
+        let (moves, outcomes) = (bytes(moves), bytes(outcomes));
+        if moves.len() != outcomes.len() {
+            return Err(ReplayError::Length {
+                moves: moves.len(),
+                outcomes: outcomes.len(),
+            });
+        }
+
+        let mut run = Run::new(grid);
+        for (step, (&m, &o)) in moves.iter().zip(outcomes.iter()).enumerate() {
+            let dir = Dir::from_ascii_byte(AsciiByte(m)).ok_or(ReplayError::BadMove(m as char))?;
+            let expected = Outcome::from_byte(o).ok_or(ReplayError::BadOutcome(o as char))?;
+            let got = run.apply(dir).outcome();
+            if got != expected {
+                return Err(ReplayError::Diverged {
+                    step,
+                    expected,
+                    got,
+                });
+            }
+        }
+        Ok(run)
+    }
+}
+
+const REPLAY_WIDTH: usize = 70;
+
+#[derive(Debug, PartialEq)]
+enum ReplayError {
+    Sections(usize),
+    BadGrid(String),
+    BadMove(char),
+    BadOutcome(char),
+    Length {
+        moves: usize,
+        outcomes: usize,
+    },
+    Diverged {
+        step: usize,
+        expected: Outcome,
+        got: Outcome,
+    },
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Sections(n) => write!(f, "expected 3 sections, found {}", n),
+            ReplayError::BadGrid(grid) => write!(f, "bad warehouse:\n{}", grid),
+            ReplayError::BadMove(c) => write!(f, "bad move '{}'", c),
+            ReplayError::BadOutcome(c) => write!(f, "bad outcome '{}'", c),
+            ReplayError::Length { moves, outcomes } => {
+                write!(f, "{} moves but {} outcomes", moves, outcomes)
+            }
+            ReplayError::Diverged {
+                step,
+                expected,
+                got,
+            } => write!(f, "step {}: expected {:?}, got {:?}", step, expected, got),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// A key press in interactive mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Key {
+    Move(Dir),
+    Undo,
+    Redo,
+    Start,
+    End,
+    Load,
+    Quit,
+}
+
+/// Reads one key, skipping bytes that mean nothing. Arrows arrive as
+/// `ESC [ A`..`ESC [ D`; the byte after a lone `ESC` is read as a key of its
+/// own. End of input counts as quitting.
+fn read_key<R: Read>(input: &mut R) -> std::io::Result<Key> {
+    let mut next = || -> std::io::Result<Option<u8>> {
+        let mut b = [0];
+        Ok((input.read(&mut b)? == 1).then_some(b[0]))
+    };
+    let mut held: Option<u8> = None;
+    loop {
+        let Some(b) = held.take().map_or_else(&mut next, |b| Ok(Some(b)))? else {
+            return Ok(Key::Quit);
+        };
+        let key = match b {
+            0x1b => {
+                match next()? {
+                    Some(b'[') => {}
+                    other => {
+                        held = other;
+                        continue;
+                    }
                 }
-                State::Robot => {
-                    grid[Coord::new(r, 2 * c)] = StatePart2::Robot;
-                    grid[Coord::new(r, 2 * c + 1)] = StatePart2::Empty;
+                match next()? {
+                    Some(b'A') => Key::Move(Dir::Up),
+                    Some(b'B') => Key::Move(Dir::Down),
+                    Some(b'C') => Key::Move(Dir::Right),
+                    Some(b'D') => Key::Move(Dir::Left),
+                    _ => continue,
                 }
             }
+            b'w' => Key::Move(Dir::Up),
+            b's' => Key::Move(Dir::Down),
+            b'a' => Key::Move(Dir::Left),
+            b'd' => Key::Move(Dir::Right),
+            b'u' => Key::Undo,
+            b'n' | b'r' => Key::Redo,
+            b'g' => Key::Start,
+            b'G' => Key::End,
+            b'l' => Key::Load,
+            // ctrl-c and ctrl-d don't signal in raw mode
+            b'q' | 3 | 4 => Key::Quit,
+            _ => continue,
+        };
+        return Ok(key);
+    }
+}
+
+/// Queues `program` as redoable steps from the current state, so stepping
+/// through it is just redo.
+fn load<S: Warehouse>(run: &mut Run<S>, program: &[Dir15]) {
+    let at = run.cursor;
+    for dir in program {
+        run.apply(dir.0);
+    }
+    run.seek(at);
+}
+
+/// Draws the warehouse with the cells the last step changed in reverse
+/// video. Raw mode doesn't translate `\n`, hence the `\r`s.
+fn draw<S: Warehouse, W: Write>(run: &Run<S>, out: &mut W) -> std::io::Result<()> {
+    let changed: Vec<Coord> = match run.cursor {
+        0 => vec![],
+        n => run.steps[n - 1].changes.iter().map(|c| c.pos).collect(),
+    };
+    write!(out, "\x1b[2J\x1b[H")?;
+    for row in 0..run.grid.height {
+        for col in 0..run.grid.width {
+            let pos = Coord::new(row, col);
+            if changed.contains(&pos) {
+                write!(out, "\x1b[7m{}\x1b[0m", run.grid[pos])?;
+            } else {
+                write!(out, "{}", run.grid[pos])?;
+            }
         }
+        write!(out, "\r\n")?;
     }
+    write!(
+        out,
+        "\r\nstep {}/{}  GPS {}\r\n",
+        run.cursor,
+        run.steps.len(),
+        gps(&run.grid)
+    )?;
+    write!(
+        out,
+        "arrows/wasd move  u undo  n next  g start  G end  l load instructions  q quit\r\n"
+    )?;
+    out.flush()
+}
 
-    dump(&grid);
+/// Plays keys from `input` until quit, redrawing after each one.
+fn play<S: Warehouse, R: Read, W: Write>(
+    run: &mut Run<S>,
+    program: &[Dir15],
+    input: &mut R,
+    out: &mut W,
+) -> std::io::Result<()> {
+    loop {
+        draw(run, out)?;
+        match read_key(input)? {
+            Key::Move(dir) => {
+                run.apply(dir);
+            }
+            Key::Undo => {
+                run.undo();
+            }
+            Key::Redo => {
+                run.redo();
+            }
+            Key::Start => run.seek(0),
+            Key::End => run.seek(run.steps.len()),
+            Key::Load => load(run, program),
+            Key::Quit => return Ok(()),
+        }
+    }
+}
 
-    let mut robot_pos = grid
-        .find_with(|s| matches!(*s, StatePart2::Robot))
-        .pop()
-        .unwrap();
+fn stty(args: &[&str]) -> std::io::Result<String> {
+    let out = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !out.status.success() {
+        return Err(std::io::Error::other(
+            String::from_utf8_lossy(&out.stderr).into_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
 
-    for dir in directions {
-        robot_pos = apply_dir_part2(&mut grid, robot_pos, dir.0);
-        //println!("after applying {dir:?}:");
-        //dump(&grid);
+/// Raw, unechoed terminal input until dropped.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> std::io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(Self { saved })
     }
-    let res: usize = grid
-        .find_with(|state| matches!(*state, StatePart2::BoxL))
-        .iter()
-        .map(|c| c.row * 100 + c.col)
-        .sum();
-    println!("{}", res);
 }
 
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Plays the puzzle warehouse from the keyboard, with its instructions
+/// loaded so `n` steps through them.
+fn interactive(wide: bool) -> std::io::Result<()> {
+    let input = std::fs::read_to_string("input/d15.txt").unwrap();
+    //let input = TEST1;
+    let (grid, program) = parse(&input);
+    let _raw = RawMode::enable()?;
+    let (mut stdin, mut stdout) = (std::io::stdin().lock(), std::io::stdout().lock());
+    if wide {
+        let mut run = Run::new(widen(&grid));
+        load(&mut run, &program);
+        play(&mut run, &program, &mut stdin, &mut stdout)
+    } else {
+        let mut run = Run::new(grid);
+        load(&mut run, &program);
+        play(&mut run, &program, &mut stdin, &mut stdout)
+    }
+}
+
+/// `d15` solves part 2; `d15 play [--wide]` opens the interactive mode on
+/// the narrow or wide warehouse.
 fn main() {
-    part2();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => part2(),
+        ["play"] => interactive(false).unwrap(),
+        ["play", "--wide"] => interactive(true).unwrap(),
+        _ => {
+            eprintln!("usage: d15 [play [--wide]]");
+            std::process::exit(2);
+        }
+    }
 }
 
 static TEST: &str = r#"
@@ -376,3 +877,126 @@ static TEST2: &str = r#"
 #######
 
 <vv<<^^<<^^"#;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run<S: Warehouse>(grid: BasicGrid<S>, directions: &[Dir15]) -> Run<S> {
+        let mut run = Run::new(grid);
+        for dir in directions {
+            run.apply(dir.0);
+        }
+        run
+    }
+
+    #[test]
+    fn test_history() {
+        let (grid, directions) = parse(TEST1);
+        let mut narrow = run(grid.clone(), &directions);
+        assert_eq!(gps(&narrow.grid), 10092);
+        let mut wide = run(widen(&grid), &directions);
+        assert_eq!(gps(&wide.grid), 9021);
+
+        let end = narrow.grid.clone();
+        narrow.seek(0);
+        assert_eq!(render(&narrow.grid), render(&grid));
+        assert_eq!(render(&narrow.initial()), render(&grid));
+        assert!(!narrow.undo());
+        narrow.seek(directions.len());
+        assert_eq!(render(&narrow.grid), render(&end));
+        assert!(!narrow.redo());
+
+        // diffs agree with seeking, in both directions
+        let (a, b) = (100, 350);
+        narrow.seek(a);
+        let mut at_a = narrow.grid.clone();
+        narrow.seek(b);
+        for c in narrow.diff(a, b).unwrap() {
+            assert_eq!(at_a[c.pos], c.before);
+            assert_eq!(narrow.grid[c.pos], c.after);
+            at_a[c.pos] = c.after;
+        }
+        assert_eq!(render(&at_a), render(&narrow.grid));
+        assert_eq!(
+            narrow.diff(b, a).unwrap()[0].before,
+            narrow.diff(a, b).unwrap()[0].after
+        );
+        assert_eq!(narrow.diff(a, a), Some(vec![]));
+
+        // applying after an undo drops the redo tail
+        wide.seek(10);
+        wide.apply(Dir::Up);
+        assert_eq!(wide.steps.len(), 11);
+        assert!(!wide.redo());
+    }
+
+    #[test]
+    fn test_diff_out_of_range() {
+        let (grid, program) = parse(TEST2);
+        let run = run(grid, &program);
+        assert!(run.diff(0, 11).is_some());
+        assert_eq!(run.diff(0, 12), None);
+        assert_eq!(run.diff(12, 3), None);
+    }
+
+    #[test]
+    fn test_play() {
+        let (grid, program) = parse(TEST2);
+        let mut run = Run::new(widen(&grid));
+        load(&mut run, &program);
+        assert_eq!(run.cursor, 0);
+        assert_eq!(run.steps.len(), program.len());
+
+        // two instructions, undo one, then a manual move left via arrow
+        // and one up via wasd, which replaces the rest of the program
+        let mut keys: &[u8] = b"nnu\x1b[Dwxq";
+        let mut screen = vec![];
+        play(&mut run, &program, &mut keys, &mut screen).unwrap();
+        let dirs: Vec<Dir> = run.steps.iter().map(|s| s.dir).collect();
+        assert_eq!(dirs, [Dir::Left, Dir::Left, Dir::Up]);
+        assert_eq!(run.cursor, 3);
+        let screen = String::from_utf8(screen).unwrap();
+        assert!(screen.ends_with("q quit\r\n"));
+        assert!(screen.contains(&format!("step 3/3  GPS {}", gps(&run.grid))));
+
+        let mut keys: &[u8] = b"\x1b[A\x1bxG\x1bu\x1b\x1b[B\x1bq";
+        assert_eq!(read_key(&mut keys).unwrap(), Key::Move(Dir::Up));
+        assert_eq!(read_key(&mut keys).unwrap(), Key::End);
+        assert_eq!(read_key(&mut keys).unwrap(), Key::Undo);
+        assert_eq!(read_key(&mut keys).unwrap(), Key::Move(Dir::Down));
+        assert_eq!(read_key(&mut keys).unwrap(), Key::Quit);
+        let mut keys: &[u8] = b"\x1b";
+        assert_eq!(read_key(&mut keys).unwrap(), Key::Quit);
+    }
+
+    #[test]
+    fn test_replay() {
+        let (grid, directions) = parse(TEST1);
+        let wide = run(widen(&grid), &directions);
+        let mut file = vec![];
+        wide.write_replay(&mut file).unwrap();
+        let file = String::from_utf8(file).unwrap();
+        let replayed: Run<StatePart2> = Run::read_replay(&file).unwrap();
+        assert_eq!(render(&replayed.grid), render(&wide.grid));
+        assert_eq!(replayed.cursor, directions.len());
+
+        let crlf: Run<StatePart2> = Run::read_replay(&file.replace('\n', "\r\n")).unwrap();
+        assert_eq!(render(&crlf.grid), render(&wide.grid));
+
+        let narrow: Result<Run<State>, _> = Run::read_replay(&file);
+        assert!(matches!(narrow, Err(ReplayError::BadGrid(_))));
+
+        let first = file.rfind("\n\n").unwrap() + 2;
+        let mut tampered = file.clone().into_bytes();
+        tampered[first] = match tampered[first] {
+            b'b' => b'm',
+            _ => b'b',
+        };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(matches!(
+            Run::<StatePart2>::read_replay(&tampered),
+            Err(ReplayError::Diverged { step: 0, .. })
+        ));
+    }
+}
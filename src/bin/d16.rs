@@ -5,34 +5,23 @@ use std::{
 };
 
 use slotmap::{new_key_type, SecondaryMap, SlotMap};
-use utils::{BasicGrid, Coord, Dir};
+use utils::{BasicGrid, Coord, Dir, GridCell};
 
 use owo_colors::OwoColorize;
 use rand::prelude::*;
 
+#[derive(GridCell)]
 enum State {
+    #[cell('#')]
     Wall,
+    #[cell('.')]
     Empty,
+    #[cell('S')]
     Start,
+    #[cell('E')]
     End,
 }
 
-impl From<u8> for State {
-    fn from(value: u8) -> Self {
-        if value == b'#' {
-            State::Wall
-        } else if value == b'.' {
-            State::Empty
-        } else if value == b'S' {
-            State::Start
-        } else if value == b'E' {
-            State::End
-        } else {
-            panic!("invalid char: {}", value)
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 struct PartialPath {
     steps: Vec<Dir>,
@@ -4,28 +4,20 @@ use std::{
 };
 
 use itertools::Itertools;
-use utils::{BasicGrid, Coord, Dir};
+use utils::{BasicGrid, Coord, Dir, GridCell};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, GridCell)]
 enum GridPoint {
+    #[cell('#')]
     Wall,
+    #[cell('.')]
     Open,
+    #[cell('S')]
     Start,
+    #[cell('E')]
     End,
 }
 
-impl From<u8> for GridPoint {
-    fn from(value: u8) -> Self {
-        match value {
-            b'#' => Self::Wall,
-            b'.' => Self::Open,
-            b'S' => Self::Start,
-            b'E' => Self::End,
-            _ => panic!(),
-        }
-    }
-}
-
 fn trace_path(grid: &BasicGrid<GridPoint>) -> (Vec<Coord>, HashMap<Coord, usize>) {
     let mut steps: HashMap<Coord, usize> = Default::default();
 
@@ -1,7 +1,11 @@
 #![allow(dead_code)]
 
-use std::{collections::HashSet, fmt::Display};
-use utils::*;
+use rayon::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+use utils::{sim::*, *};
 
 fn _part1(data: &str) {
     let mut input: Vec<&str> = vec![];
@@ -43,10 +47,14 @@ impl VisitState {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, GridCell)]
+#[grid_cell(no_display)]
 enum CellState {
+    #[cell('^')]
     Start(Vec<VisitState>),
+    #[cell('#')]
     Obstructed,
+    #[cell('.')]
     Open(Vec<VisitState>),
 }
 
@@ -100,81 +108,495 @@ impl Display for CellState {
     }
 }
 
-impl From<u8> for CellState {
-    fn from(value: u8) -> Self {
-        if value == b'#' {
-            CellState::Obstructed
-        } else if value == b'^' {
-            CellState::Start(vec![])
-        } else {
-            CellState::Open(vec![])
+/// For every cell and heading, the cell where the guard stops in front of
+/// the next obstacle, or `None` if it walks off the map.
+struct JumpTable {
+    stops: BasicGrid<[Option<Coord>; 4]>,
+}
+
+impl JumpTable {
+    fn new(blocked: &BasicGrid<bool>) -> Self {
+        let (w, h) = (blocked.width, blocked.height);
+        let mut stops: BasicGrid<[Option<Coord>; 4]> = BasicGrid::new_default(w, h);
+        for row in 0..h {
+            let mut stop = None;
+            for col in 0..w {
+                let c = Coord::new(row, col);
+                if blocked[c] {
+                    stop = Some(Coord::new(row, col + 1));
+                }
+                stops[c][Dir::Left.index()] = stop;
+            }
+            let mut stop = None;
+            for col in (0..w).rev() {
+                let c = Coord::new(row, col);
+                if blocked[c] {
+                    stop = col.checked_sub(1).map(|col| Coord::new(row, col));
+                }
+                stops[c][Dir::Right.index()] = stop;
+            }
+        }
+        for col in 0..w {
+            let mut stop = None;
+            for row in 0..h {
+                let c = Coord::new(row, col);
+                if blocked[c] {
+                    stop = Some(Coord::new(row + 1, col));
+                }
+                stops[c][Dir::Up.index()] = stop;
+            }
+            let mut stop = None;
+            for row in (0..h).rev() {
+                let c = Coord::new(row, col);
+                if blocked[c] {
+                    stop = row.checked_sub(1).map(|row| Coord::new(row, col));
+                }
+                stops[c][Dir::Down.index()] = stop;
+            }
         }
+        Self { stops }
     }
-}
 
-fn would_loop_if_turn(
-    grid: &BasicGrid<CellState>,
-    mut pos: Coord,
-    next_pos: Coord,
-    dir: Dir,
-    mut step: usize,
-) -> bool {
-    let mut loop_grid = (*grid).clone();
-    loop_grid[next_pos] = CellState::Obstructed;
-    let mut seen: HashSet<(Coord, Dir)> = Default::default();
-    seen.insert((pos, dir));
-    let mut dir = dir.turn_right();
-    while let Some(next_pos) = loop_grid.next_pos(pos, dir) {
-        if matches!(loop_grid[next_pos], CellState::Obstructed) {
-            dir = dir.turn_right();
-            continue;
+    /// How far `to` lies ahead of `from` when facing `dir`, if it's on that ray.
+    fn ahead(from: Coord, dir: Dir, to: Coord) -> Option<usize> {
+        match dir {
+            Dir::Up => (to.col == from.col && to.row <= from.row).then(|| from.row - to.row),
+            Dir::Down => (to.col == from.col && to.row >= from.row).then(|| to.row - from.row),
+            Dir::Left => (to.row == from.row && to.col <= from.col).then(|| from.col - to.col),
+            Dir::Right => (to.row == from.row && to.col >= from.col).then(|| to.col - from.col),
         }
-        if seen.contains(&(next_pos, dir)) {
-            return true;
+    }
+
+    /// Where the guard stops from `pos` facing `dir` with one extra
+    /// obstruction placed at `obstruction`.
+    fn stop(&self, pos: Coord, dir: Dir, obstruction: Coord) -> Option<Coord> {
+        let natural = self.stops[pos][dir.index()];
+        match Self::ahead(pos, dir, obstruction) {
+            Some(k) if k > 0 && natural.is_none_or(|s| Self::ahead(pos, dir, s).unwrap() >= k) => {
+                Some(match dir {
+                    Dir::Up => Coord::new(obstruction.row + 1, obstruction.col),
+                    Dir::Down => Coord::new(obstruction.row - 1, obstruction.col),
+                    Dir::Left => Coord::new(obstruction.row, obstruction.col + 1),
+                    Dir::Right => Coord::new(obstruction.row, obstruction.col - 1),
+                })
+            }
+            _ => natural,
         }
-        seen.insert((next_pos, dir));
-        loop_grid[next_pos].visit(step, dir);
-        pos = next_pos;
-        step += 1;
     }
-    false
-}
 
-fn part2(data: &str) {
-    let mut input: Vec<&str> = vec![];
-    for line in data.lines().map(str::trim) {
-        input.push(line);
+    /// Follows the guard from `pos` facing `dir`, jumping from turn to turn,
+    /// and reports whether it ends up in a loop.
+    fn loops_with(
+        &self,
+        mut pos: Coord,
+        mut dir: Dir,
+        obstruction: Coord,
+        seen: &mut DirBitGrid,
+    ) -> bool {
+        seen.reset();
+        while let Some(stop) = self.stop(pos, dir, obstruction) {
+            if !seen.set(stop, dir) {
+                return true;
+            }
+            pos = stop;
+            dir = dir.turn_right();
+        }
+        false
     }
-    let mut grid: utils::BasicGrid<CellState> = utils::BasicGrid::new(&input);
+}
+
+/// The guard's state just before it first enters `obstruction`, which is
+/// where a new obstruction would be placed.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    pos: Coord,
+    dir: Dir,
+    step: usize,
+    obstruction: Coord,
+}
+
+/// Walks the guard's route and returns every candidate where a single new
+/// obstruction would trap it in a loop, in the order the route reaches them.
+fn loop_candidates(grid: &mut BasicGrid<CellState>) -> Vec<Candidate> {
+    let table = JumpTable::new(&grid.map(|c| matches!(c, CellState::Obstructed)));
     let start_candidates = grid.find_with(|v| matches!(v, CellState::Start(_)));
     assert!(start_candidates.len() == 1);
     let mut pos = start_candidates[0];
     let mut dir = Dir::Up;
     let mut steps: usize = 0;
-    let mut loop_pos: HashSet<Coord> = Default::default();
-    let mut seen: HashSet<Coord> = Default::default();
-    seen.insert(pos);
+    let mut candidates: Vec<Candidate> = vec![];
+    let mut seen = BitGrid::for_grid(grid);
+    seen.set(pos);
     while let Some(next_pos) = grid.next_pos(pos, dir) {
         if matches!(grid[next_pos], CellState::Obstructed) {
             dir = dir.turn_right();
             continue;
-        } else if !seen.contains(&next_pos) && would_loop_if_turn(&grid, pos, next_pos, dir, steps)
-        {
-            println!("loop at {:?}", next_pos);
-            loop_pos.insert(next_pos);
+        } else if seen.set(next_pos) {
+            candidates.push(Candidate {
+                pos,
+                dir,
+                step: steps,
+                obstruction: next_pos,
+            });
         }
         grid[next_pos].visit(steps, dir);
-        seen.insert(next_pos);
         pos = next_pos;
         steps += 1;
     }
 
+    let (w, h) = (grid.width, grid.height);
+    candidates
+        .par_iter()
+        .map_init(
+            || DirBitGrid::new(w, h),
+            |seen, c| {
+                table
+                    .loops_with(c.pos, c.dir, c.obstruction, seen)
+                    .then_some(*c)
+            },
+        )
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+fn loop_obstructions(grid: &mut BasicGrid<CellState>) -> Vec<Coord> {
+    loop_candidates(grid)
+        .into_iter()
+        .map(|c| c.obstruction)
+        .collect()
+}
+
+/// The loop a candidate obstruction traps the guard in. Steps are numbered
+/// like the guard's original route: step `n` is the `n`th move.
+#[derive(Debug)]
+struct LoopReport {
+    obstruction: Coord,
+    /// first step of the loop
+    entry: usize,
+    /// moves per lap
+    length: usize,
+    /// each move from the obstruction until the loop closes
+    moves: Vec<(Coord, VisitState)>,
+}
+
+impl LoopReport {
+    /// The distinct cells on the loop, in the order the guard reaches them.
+    fn cells(&self) -> Vec<Coord> {
+        let mut seen: HashSet<Coord> = Default::default();
+        self.moves
+            .iter()
+            .filter(|(_, v)| v.step >= self.entry)
+            .filter_map(|(c, _)| seen.insert(*c).then_some(*c))
+            .collect()
+    }
+}
+
+fn trace_loop(blocked: &BasicGrid<bool>, candidate: &Candidate) -> LoopReport {
+    let (mut pos, mut dir, mut step) = (candidate.pos, candidate.dir, candidate.step);
+    // state index n is where the guard is before move n
+    let mut states: HashMap<(Coord, Dir), usize> = Default::default();
+    states.insert((pos, dir), step);
+    let mut moves: Vec<(Coord, VisitState)> = vec![];
+    loop {
+        let next_pos = blocked.next_pos(pos, dir).expect("candidate does not loop");
+        if blocked[next_pos] || next_pos == candidate.obstruction {
+            dir = dir.turn_right();
+            continue;
+        }
+        moves.push((next_pos, VisitState::new(step, dir)));
+        step += 1;
+        if let Some(first) = states.insert((next_pos, dir), step) {
+            return LoopReport {
+                obstruction: candidate.obstruction,
+                entry: first,
+                length: step - first,
+                moves,
+            };
+        }
+        pos = next_pos;
+    }
+}
+
+fn loop_reports(grid: &mut BasicGrid<CellState>) -> Vec<LoopReport> {
+    let candidates = loop_candidates(grid);
+    let blocked = grid.map(|c| matches!(c, CellState::Obstructed));
+    candidates
+        .par_iter()
+        .map(|c| trace_loop(&blocked, c))
+        .collect()
+}
+
+/// Draws the loop with the usual `|`, `-` and `+` traces and the new
+/// obstruction as `O`.
+fn render_loop(grid: &BasicGrid<CellState>, report: &LoopReport) {
+    let mut loop_grid = grid.map(|c| match c {
+        CellState::Obstructed => CellState::Obstructed,
+        CellState::Start(_) => CellState::Start(vec![]),
+        CellState::Open(_) => CellState::Open(vec![]),
+    });
+    for (c, v) in report.moves.iter().filter(|(_, v)| v.step >= report.entry) {
+        loop_grid[*c].visit(v.step, v.dir);
+    }
+    let mut marker = BitGrid::for_grid(grid);
+    marker.set(report.obstruction);
+    print!("{}", marker.overlay(&loop_grid, AsciiByte(b'O')));
+}
+
+fn part2(data: &str) {
+    let mut input: Vec<&str> = vec![];
+    for line in data.lines().map(str::trim) {
+        input.push(line);
+    }
+    let mut grid: utils::BasicGrid<CellState> = utils::BasicGrid::new(&input);
+    let loop_pos = loop_obstructions(&mut grid);
+    for c in loop_pos.iter() {
+        println!("loop at {:?}", c);
+    }
     println!("{} loops", loop_pos.len());
 }
+
+fn loop_report(data: &str, show: Option<Coord>) {
+    let input: Vec<&str> = data.lines().map(str::trim).collect();
+    let mut grid: BasicGrid<CellState> = BasicGrid::new(&input);
+    let reports = loop_reports(&mut grid);
+    println!(
+        "{:>12} {:>8} {:>8} {:>8}",
+        "obstruction", "entry", "length", "cells"
+    );
+    for r in reports.iter() {
+        println!(
+            "{:>12} {:>8} {:>8} {:>8}",
+            format!("({},{})", r.obstruction.row, r.obstruction.col),
+            r.entry,
+            r.length,
+            r.cells().len()
+        );
+    }
+    if let Some(r) = show.and_then(|c| reports.iter().find(|r| r.obstruction == c)) {
+        println!();
+        render_loop(&grid, r);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Turn {
+    Left,
+    #[default]
+    Right,
+    Reverse,
+}
+
+impl Turn {
+    fn apply(self, dir: Dir) -> Dir {
+        match self {
+            Turn::Left => dir.turn_left(),
+            Turn::Right => dir.turn_right(),
+            Turn::Reverse => dir.reverse(),
+        }
+    }
+}
+
+/// How guards in a patrol behave. Each guard starts at an arrow glyph
+/// (`^`, `v`, `<`, `>`) facing the way it points.
+#[derive(Debug, Clone, Copy, Default)]
+struct PatrolRules {
+    turn: Turn,
+}
+
+#[derive(Debug, Clone)]
+struct Guard {
+    pos: Coord,
+    dir: Dir,
+    exited: Option<usize>,
+    visited: BitGrid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Collision {
+    step: usize,
+    cell: Coord,
+    guards: Vec<usize>,
+}
+
+/// Several guards moving in lockstep. Each step every guard still on the
+/// map turns until the cell ahead is free of obstacles and other guards,
+/// then moves into it. Guards that pick the same cell collide and stay put.
+#[derive(Clone)]
+struct Patrol<'a> {
+    blocked: &'a BasicGrid<bool>,
+    rules: PatrolRules,
+    guards: Vec<Guard>,
+    step: usize,
+    collisions: Vec<Collision>,
+}
+
+impl<'a> Patrol<'a> {
+    fn new(grid: &BasicGrid<AsciiByte>, blocked: &'a BasicGrid<bool>, rules: PatrolRules) -> Self {
+        let guards = grid
+            .row_major_iter()
+            .filter_map(|c| {
+                Dir::from_ascii_byte(grid[c]).map(|dir| {
+                    let mut visited = BitGrid::for_grid(grid);
+                    visited.set(c);
+                    Guard {
+                        pos: c,
+                        dir,
+                        exited: None,
+                        visited,
+                    }
+                })
+            })
+            .collect();
+        Self {
+            blocked,
+            rules,
+            guards,
+            step: 0,
+            collisions: vec![],
+        }
+    }
+
+    fn occupied(&self, c: Coord) -> bool {
+        self.guards.iter().any(|g| g.exited.is_none() && g.pos == c)
+    }
+
+    /// Where guard `i` wants to go: `Some(None)` to walk off the map,
+    /// `Some(Some(c))` to move to `c`, `None` if boxed in.
+    fn target(&mut self, i: usize) -> Option<Option<Coord>> {
+        for _ in 0..4 {
+            let g = &self.guards[i];
+            match self.blocked.next_pos(g.pos, g.dir) {
+                None => return Some(None),
+                Some(c) if !self.blocked[c] && !self.occupied(c) => return Some(Some(c)),
+                Some(_) => self.guards[i].dir = self.rules.turn.apply(self.guards[i].dir),
+            }
+        }
+        None
+    }
+}
+
+impl Simulation for Patrol<'_> {
+    type Key = Vec<(Coord, Dir, bool)>;
+
+    fn step(&mut self) -> bool {
+        if self.guards.iter().all(|g| g.exited.is_some()) {
+            return false;
+        }
+        let mut targets: Vec<(usize, Option<Coord>)> = vec![];
+        for i in 0..self.guards.len() {
+            if self.guards[i].exited.is_none() {
+                if let Some(t) = self.target(i) {
+                    targets.push((i, t));
+                }
+            }
+        }
+        for (i, t) in targets.iter() {
+            match t {
+                None => self.guards[*i].exited = Some(self.step + 1),
+                Some(c) => {
+                    let claimants: Vec<usize> = targets
+                        .iter()
+                        .filter(|(_, other)| *other == Some(*c))
+                        .map(|(j, _)| *j)
+                        .collect();
+                    if claimants.len() > 1 {
+                        if claimants[0] == *i {
+                            self.collisions.push(Collision {
+                                step: self.step,
+                                cell: *c,
+                                guards: claimants,
+                            });
+                        }
+                    } else {
+                        let g = &mut self.guards[*i];
+                        g.pos = *c;
+                        g.visited.set(*c);
+                    }
+                }
+            }
+        }
+        self.step += 1;
+        true
+    }
+
+    fn key(&self) -> Self::Key {
+        self.guards
+            .iter()
+            .map(|g| (g.pos, g.dir, g.exited.is_some()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Exited { step: usize },
+    Looped { entry: usize, period: usize },
+}
+
+#[derive(Debug)]
+struct GuardReport {
+    start: Coord,
+    visited: usize,
+    outcome: Outcome,
+}
+
+fn run_patrol(data: &str, rules: PatrolRules) -> (Vec<GuardReport>, Vec<Collision>) {
+    let input: Vec<&str> = data.lines().map(str::trim).collect();
+    let grid: BasicGrid<AsciiByte> = BasicGrid::new(&input);
+    let blocked = grid.map(|b| *b == AsciiByte(b'#'));
+    let patrol = Patrol::new(&grid, &blocked, rules);
+    let starts: Vec<Coord> = patrol.guards.iter().map(|g| g.pos).collect();
+
+    let mut runner = Runner::new(patrol);
+    let cycle = runner.find_cycle();
+    // one full lap of any cycle is enough to see every cell and collision
+    runner.run(cycle.map_or(usize::MAX, |c| c.prefix + c.period));
+    let patrol = runner.into_state();
+
+    let reports = patrol
+        .guards
+        .iter()
+        .zip(starts)
+        .map(|(g, start)| GuardReport {
+            start,
+            visited: g.visited.count(),
+            outcome: match (g.exited, cycle) {
+                (Some(step), _) => Outcome::Exited { step },
+                (None, Some(c)) => Outcome::Looped {
+                    entry: c.prefix,
+                    period: c.period,
+                },
+                (None, None) => unreachable!("simulation ended with a guard on the map"),
+            },
+        })
+        .collect();
+    (reports, patrol.collisions)
+}
+
+fn patrol(data: &str, rules: PatrolRules) {
+    let (reports, collisions) = run_patrol(data, rules);
+    for (i, r) in reports.iter().enumerate() {
+        println!(
+            "guard {} from {:?}: visited {} cells, {:?}",
+            i, r.start, r.visited, r.outcome
+        );
+    }
+    for c in collisions.iter() {
+        println!(
+            "step {}: guards {:?} collided at {:?}",
+            c.step, c.guards, c.cell
+        );
+    }
+}
+
 fn main() {
     let data = std::fs::read_to_string("input/d6.txt").unwrap();
     //let data = TEST;
     part2(&data);
+    //patrol(&data, PatrolRules::default());
+    //loop_report(&data, Some(Coord::new(6, 3)));
 }
 
 static TEST: &str = "....#.....
@@ -187,3 +609,66 @@ static TEST: &str = "....#.....
 ........#.
 #.........
 ......#...";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loop_obstructions() {
+        let input: Vec<&str> = TEST.lines().collect();
+        let mut grid: BasicGrid<CellState> = BasicGrid::new(&input);
+        let found = loop_obstructions(&mut grid);
+        assert_eq!(found.len(), 6);
+        assert!(found.contains(&Coord::new(6, 3)));
+    }
+
+    #[test]
+    fn test_loop_report() {
+        let input: Vec<&str> = TEST.lines().collect();
+        let mut grid: BasicGrid<CellState> = BasicGrid::new(&input);
+        let reports = loop_reports(&mut grid);
+        assert_eq!(reports.len(), 6);
+        let r = reports
+            .iter()
+            .find(|r| r.obstruction == Coord::new(6, 3))
+            .unwrap();
+        assert_eq!(r.length, 18);
+        assert_eq!(r.cells().len(), 18);
+        assert!(r.cells().contains(&Coord::new(6, 4)));
+    }
+
+    #[test]
+    fn test_patrol() {
+        let (reports, collisions) = run_patrol(TEST, PatrolRules::default());
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].visited, 41);
+        assert!(matches!(reports[0].outcome, Outcome::Exited { .. }));
+        assert!(collisions.is_empty());
+
+        let looping = TEST.replace(".#..^.....", ".#.#^.....");
+        let (reports, _) = run_patrol(&looping, PatrolRules::default());
+        assert!(matches!(reports[0].outcome, Outcome::Looped { .. }));
+
+        let (reports, collisions) = run_patrol("#>..<#", PatrolRules::default());
+        assert_eq!(reports[0].visited, 2);
+        assert_eq!(collisions.len(), 0);
+        let (_, collisions) = run_patrol("#>.<#", PatrolRules::default());
+        assert_eq!(
+            collisions,
+            vec![Collision {
+                step: 0,
+                cell: Coord::new(0, 2),
+                guards: vec![0, 1]
+            }]
+        );
+
+        let (reports, _) = run_patrol(
+            "#.^.#",
+            PatrolRules {
+                turn: Turn::Reverse,
+            },
+        );
+        assert_eq!(reports[0].outcome, Outcome::Exited { step: 1 });
+    }
+}
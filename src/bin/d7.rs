@@ -1,93 +1,420 @@
 #![allow(dead_code)]
 
-use std::str::FromStr;
+use rayon::prelude::*;
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
 struct Item {
     total: u64,
-    components: Vec<u16>,
+    components: Vec<u64>,
 }
 
-fn check_part1(total: u64, acc: u64, items: &[u16]) -> bool {
-    if acc > total {
-        false
-    } else if items.is_empty() {
-        return total == acc;
-    } else if check_part1(total, acc + items[0] as u64, &items[1..]) {
-        return true;
-    } else {
-        return check_part1(total, acc * items[0] as u64, &items[1..]);
+/// What undoing an operator from a known result tells us about its left
+/// operand.
+#[derive(Debug, PartialEq, Eq)]
+enum Inverse {
+    /// no left operand gives the result
+    None,
+    /// exactly this left operand gives the result
+    Exact(u128),
+    /// possibly several; the solver has to search forward instead
+    Unknown,
+}
+
+trait Operator: Sync {
+    fn symbol(&self) -> &'static str;
+
+    /// `acc op x`, or `None` if it overflows or is undefined.
+    fn apply(&self, acc: u128, x: u128) -> Option<u128>;
+
+    /// The `acc` for which `acc op x == target`.
+    fn undo(&self, target: u128, x: u128) -> Inverse;
+
+    /// True if `acc op x` is never less than `acc`, so an accumulator that
+    /// has passed the total can be dropped.
+    fn never_decreases(&self, x: u128) -> bool;
+}
+
+struct Add;
+struct Mul;
+struct Concat;
+struct Sub;
+struct Div;
+struct Xor;
+
+fn exact(v: Option<u128>) -> Inverse {
+    v.map_or(Inverse::None, Inverse::Exact)
+}
+
+fn digit_scale(x: u128) -> u128 {
+    10_u128.pow(x.checked_ilog10().unwrap_or(0) + 1)
+}
+
+impl Operator for Add {
+    fn symbol(&self) -> &'static str {
+        "+"
+    }
+    fn apply(&self, acc: u128, x: u128) -> Option<u128> {
+        acc.checked_add(x)
+    }
+    fn undo(&self, target: u128, x: u128) -> Inverse {
+        exact(target.checked_sub(x))
+    }
+    fn never_decreases(&self, _x: u128) -> bool {
+        true
     }
 }
 
-fn check_part2(total: u64, acc: u64, items: &[u16]) -> bool {
-    if acc > total {
-        false
-    } else if items.is_empty() {
-        return total == acc;
-    } else if check_part2(total, acc + items[0] as u64, &items[1..]) {
-        return true;
-    } else {
-        let n = items[0].ilog10();
-        return check_part2(
-            total,
-            acc * 10_u64.pow(n + 1) + items[0] as u64,
-            &items[1..],
-        );
+impl Operator for Mul {
+    fn symbol(&self) -> &'static str {
+        "*"
+    }
+    fn apply(&self, acc: u128, x: u128) -> Option<u128> {
+        acc.checked_mul(x)
+    }
+    fn undo(&self, target: u128, x: u128) -> Inverse {
+        match (target, x) {
+            (0, 0) => Inverse::Unknown,
+            (_, 0) => Inverse::None,
+            _ => exact(target.is_multiple_of(x).then(|| target / x)),
+        }
+    }
+    fn never_decreases(&self, x: u128) -> bool {
+        x >= 1
     }
 }
 
-#[derive(Debug)]
+impl Operator for Concat {
+    fn symbol(&self) -> &'static str {
+        "||"
+    }
+    fn apply(&self, acc: u128, x: u128) -> Option<u128> {
+        acc.checked_mul(digit_scale(x))?.checked_add(x)
+    }
+    fn undo(&self, target: u128, x: u128) -> Inverse {
+        let scale = digit_scale(x);
+        exact(
+            target
+                .checked_sub(x)
+                .filter(|rest| rest.is_multiple_of(scale))
+                .map(|rest| rest / scale),
+        )
+    }
+    fn never_decreases(&self, _x: u128) -> bool {
+        true
+    }
+}
+
+impl Operator for Sub {
+    fn symbol(&self) -> &'static str {
+        "-"
+    }
+    fn apply(&self, acc: u128, x: u128) -> Option<u128> {
+        acc.checked_sub(x)
+    }
+    fn undo(&self, target: u128, x: u128) -> Inverse {
+        exact(target.checked_add(x))
+    }
+    fn never_decreases(&self, x: u128) -> bool {
+        x == 0
+    }
+}
+
+impl Operator for Div {
+    fn symbol(&self) -> &'static str {
+        "/"
+    }
+    fn apply(&self, acc: u128, x: u128) -> Option<u128> {
+        acc.checked_div(x)
+    }
+    fn undo(&self, _target: u128, x: u128) -> Inverse {
+        if x == 0 {
+            Inverse::None
+        } else {
+            Inverse::Unknown
+        }
+    }
+    fn never_decreases(&self, x: u128) -> bool {
+        x <= 1
+    }
+}
+
+impl Operator for Xor {
+    fn symbol(&self) -> &'static str {
+        "^"
+    }
+    fn apply(&self, acc: u128, x: u128) -> Option<u128> {
+        Some(acc ^ x)
+    }
+    fn undo(&self, target: u128, x: u128) -> Inverse {
+        Inverse::Exact(target ^ x)
+    }
+    fn never_decreases(&self, x: u128) -> bool {
+        x == 0
+    }
+}
+
+const PART1: &[&dyn Operator] = &[&Add, &Mul];
+const PART2: &[&dyn Operator] = &[&Add, &Mul, &Concat];
+const ALL_OPS: &[&dyn Operator] = &[&Add, &Mul, &Concat, &Sub, &Div, &Xor];
+
+/// Evaluates `items` left to right with `ops[i]` between `items[i]` and
+/// `items[i + 1]`.
+fn eval(items: &[u64], ops: &[&dyn Operator]) -> Option<u128> {
+    assert_eq!(items.len(), ops.len() + 1);
+    items[1..]
+        .iter()
+        .zip(ops)
+        .try_fold(items[0] as u128, |acc, (x, op)| op.apply(acc, *x as u128))
+}
+
+/// Every value `items` can evaluate to, each with one way of getting there.
+fn forward_values<'a>(
+    items: &[u64],
+    ops: &[&'a dyn Operator],
+) -> HashMap<u128, Vec<&'a dyn Operator>> {
+    let mut values: HashMap<u128, Vec<&'a dyn Operator>> = Default::default();
+    values.insert(items[0] as u128, vec![]);
+    for x in items[1..].iter() {
+        let mut next: HashMap<u128, Vec<&'a dyn Operator>> = Default::default();
+        for (acc, path) in values {
+            for op in ops {
+                if let Some(v) = op.apply(acc, *x as u128) {
+                    next.entry(v).or_insert_with(|| {
+                        let mut p = path.clone();
+                        p.push(*op);
+                        p
+                    });
+                }
+            }
+        }
+        values = next;
+    }
+    values
+}
+
+/// Works backward from `target`, peeling operators off the right of `items`.
+fn solve_back<'a>(
+    target: u128,
+    items: &[u64],
+    ops: &[&'a dyn Operator],
+) -> Option<Vec<&'a dyn Operator>> {
+    let (x, rest) = items.split_last().unwrap();
+    if rest.is_empty() {
+        return (*x as u128 == target).then(Vec::new);
+    }
+    for op in ops {
+        let found = match op.undo(target, *x as u128) {
+            Inverse::None => None,
+            Inverse::Exact(acc) => solve_back(acc, rest, ops),
+            Inverse::Unknown => forward_values(rest, ops)
+                .into_iter()
+                .find(|(acc, _)| op.apply(*acc, *x as u128) == Some(target))
+                .map(|(_, path)| path),
+        };
+        if let Some(mut path) = found {
+            path.push(*op);
+            return Some(path);
+        }
+    }
+    None
+}
+
+impl Item {
+    /// The operators that make the components add up to the total, if any.
+    fn solve<'a>(&self, ops: &[&'a dyn Operator]) -> Option<Vec<&'a dyn Operator>> {
+        solve_back(self.total as u128, &self.components, ops)
+    }
+
+    fn expression(&self, ops: &[&dyn Operator]) -> String {
+        let mut s = self.components[0].to_string();
+        for (x, op) in self.components[1..].iter().zip(ops) {
+            s += &format!(" {} {}", op.symbol(), x);
+        }
+        s
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 enum DaySevenError {
-    Parse(String),
+    MissingColon(String),
+    BadNumber { line: String, token: String },
+    NoComponents(String),
 }
 
-impl DaySevenError {
-    fn parse_error(msg: String) -> Self {
-        Self::Parse(msg)
+impl Display for DaySevenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaySevenError::MissingColon(line) => write!(f, "no ':' in \"{}\"", line),
+            DaySevenError::BadNumber { line, token } => {
+                write!(f, "bad number \"{}\" in \"{}\"", token, line)
+            }
+            DaySevenError::NoComponents(line) => write!(f, "no components in \"{}\"", line),
+        }
     }
 }
+
+impl std::error::Error for DaySevenError {}
+
 impl FromStr for Item {
     type Err = DaySevenError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some(i) = s.find(':') {
-            let total: u64 = s[0..i]
-                .parse()
-                .map_err(|_| DaySevenError::parse_error(s.to_string()))
-                .unwrap();
-            let components = s[i + 1..]
-                .split_whitespace()
-                .map(|s| {
-                    s.parse::<u16>()
-                        .map_err(|_| DaySevenError::parse_error(format!("2: {}", s)))
+        let number = |token: &str| {
+            token
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| DaySevenError::BadNumber {
+                    line: s.to_string(),
+                    token: token.trim().to_string(),
                 })
-                .collect::<Result<Vec<u16>, DaySevenError>>()
-                .unwrap();
+        };
+        let (total, rest) = s
+            .split_once(':')
+            .ok_or_else(|| DaySevenError::MissingColon(s.to_string()))?;
+        let total = number(total)?;
+        let components = rest
+            .split_whitespace()
+            .map(number)
+            .collect::<Result<Vec<u64>, DaySevenError>>()?;
+        if components.is_empty() {
+            return Err(DaySevenError::NoComponents(s.to_string()));
+        }
+        Ok(Item { total, components })
+    }
+}
 
-            Ok(Item { total, components })
-        } else {
-            Err(DaySevenError::parse_error(s.to_string()))
+impl Item {
+    /// How many operator assignments make the components reach the total.
+    /// Assignments reaching the same accumulator at the same position are
+    /// merged, so repeated values are only explored once.
+    fn count_solutions(&self, ops: &[&dyn Operator]) -> u64 {
+        let target = self.total as u128;
+        let xs: Vec<u128> = self.components.iter().map(|x| *x as u128).collect();
+        // prunable[i]: no operator can bring the accumulator back down after
+        // component i has been applied
+        let mut prunable = vec![true; xs.len()];
+        for i in (0..xs.len() - 1).rev() {
+            prunable[i] = prunable[i + 1] && ops.iter().all(|op| op.never_decreases(xs[i + 1]));
         }
+
+        let mut counts: HashMap<u128, u64> = HashMap::from([(xs[0], 1)]);
+        for (i, x) in xs.iter().enumerate().skip(1) {
+            let mut next: HashMap<u128, u64> = Default::default();
+            for (acc, n) in counts {
+                for op in ops {
+                    match op.apply(acc, *x) {
+                        Some(v) if !(prunable[i] && v > target) => *next.entry(v).or_default() += n,
+                        _ => {}
+                    }
+                }
+            }
+            counts = next;
+        }
+        counts.get(&target).copied().unwrap_or(0)
     }
 }
 
+#[derive(Debug)]
+struct Stats {
+    total: u64,
+    components: usize,
+    assignments: u128,
+    satisfying: u64,
+    /// operators every satisfying assignment uses
+    necessary: Vec<&'static str>,
+    example: Option<String>,
+}
+
+fn item_stats(item: &Item, ops: &[&dyn Operator]) -> Stats {
+    let satisfying = item.count_solutions(ops);
+    let necessary = if satisfying == 0 {
+        vec![]
+    } else {
+        ops.iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                let without: Vec<&dyn Operator> = ops
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(j, op)| (j != *i).then_some(*op))
+                    .collect();
+                item.solve(&without).is_none()
+            })
+            .map(|(_, op)| op.symbol())
+            .collect()
+    };
+    Stats {
+        total: item.total,
+        components: item.components.len(),
+        assignments: (ops.len() as u128).pow(item.components.len() as u32 - 1),
+        satisfying,
+        necessary,
+        example: item.solve(ops).map(|s| item.expression(&s)),
+    }
+}
+
+fn report(items: &[Item], ops: &[&dyn Operator]) {
+    let stats: Vec<Stats> = items.par_iter().map(|i| item_stats(i, ops)).collect();
+    println!(
+        "{:>16} {:>5} {:>12} {:>10} {:>10}  example",
+        "total", "n", "assignments", "satisfying", "necessary"
+    );
+    for s in stats.iter() {
+        println!(
+            "{:>16} {:>5} {:>12} {:>10} {:>10}  {}",
+            s.total,
+            s.components,
+            s.assignments,
+            s.satisfying,
+            s.necessary.join(" "),
+            s.example.as_deref().unwrap_or("-")
+        );
+    }
+    println!();
+    for op in ops {
+        println!(
+            "{:>2} necessary for {} equations",
+            op.symbol(),
+            stats
+                .iter()
+                .filter(|s| s.necessary.contains(&op.symbol()))
+                .count()
+        );
+    }
+    println!(
+        "{} of {} equations solvable, calibration total {}",
+        stats.iter().filter(|s| s.satisfying > 0).count(),
+        stats.len(),
+        stats
+            .iter()
+            .filter(|s| s.satisfying > 0)
+            .map(|s| s.total)
+            .sum::<u64>()
+    );
+}
+
 fn main() {
     let data = std::fs::read_to_string("input/d7.txt").unwrap();
     //let data = TEST;
-    let check = check_part2;
-    let ans: u64 = data
+    let ops = PART2;
+    let items = match data
         .lines()
-        .map(|s| s.trim().parse::<Item>().unwrap())
-        .filter_map(|i| {
-            let v: Option<u64> = if check(i.total, i.components[0] as u64, &i.components[1..]) {
-                Some(i.total)
-            } else {
-                None
-            };
-            v
-        })
+        .map(|s| s.trim().parse::<Item>())
+        .collect::<Result<Vec<Item>, DaySevenError>>()
+    {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let ans: u64 = items
+        .par_iter()
+        .filter(|item| item.solve(ops).is_some())
+        .map(|item| item.total)
         .sum();
     println!("{}", ans);
+    //report(&items, ops);
 }
 
 static TEST: &str = "190: 10 19
@@ -99,3 +426,70 @@ static TEST: &str = "190: 10 19
 192: 17 8 14
 21037: 9 7 18 13
 292: 11 6 16 20";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn total(ops: &[&dyn Operator]) -> u64 {
+        TEST.lines()
+            .map(|s| s.parse::<Item>().unwrap())
+            .filter(|i| i.solve(ops).is_some())
+            .map(|i| i.total)
+            .sum()
+    }
+
+    #[test]
+    fn test_solve() {
+        assert_eq!(total(PART1), 3749);
+        assert_eq!(total(PART2), 11387);
+
+        let item: Item = "7290: 6 8 6 15".parse().unwrap();
+        let solution = item.solve(PART2).unwrap();
+        assert_eq!(item.expression(&solution), "6 * 8 || 6 * 15");
+        assert_eq!(eval(&item.components, &solution), Some(7290));
+    }
+
+    #[test]
+    fn test_other_ops() {
+        let item: Item = "5: 20 3 4 1".parse().unwrap();
+        let solution = item.solve(ALL_OPS).unwrap();
+        assert_eq!(eval(&item.components, &solution), Some(5));
+        assert!(item.solve(PART2).is_none());
+
+        // concatenating past u64 doesn't wrap around
+        let item: Item = "1: 18446744073709551615 99".parse().unwrap();
+        assert!(item.solve(PART2).is_none());
+    }
+
+    #[test]
+    fn test_count() {
+        let items: Vec<Item> = TEST.lines().map(|s| s.parse().unwrap()).collect();
+        let counts: Vec<u64> = items.iter().map(|i| i.count_solutions(PART1)).collect();
+        assert_eq!(counts, vec![1, 2, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(items[4].count_solutions(PART2), 1);
+
+        let stats = item_stats(&items[4], PART2);
+        assert_eq!(stats.assignments, 27);
+        assert_eq!(stats.necessary, vec!["*", "||"]);
+        // 0 * anything is 0, so every second operator works
+        let item: Item = "0: 0 5 0".parse().unwrap();
+        assert_eq!(item.count_solutions(PART1), 3);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "12 3 4".parse::<Item>().err(),
+            Some(DaySevenError::MissingColon("12 3 4".to_string()))
+        );
+        assert!(matches!(
+            "12: 3 x".parse::<Item>(),
+            Err(DaySevenError::BadNumber { token, .. }) if token == "x"
+        ));
+        assert!(matches!(
+            "12:".parse::<Item>(),
+            Err(DaySevenError::NoComponents(_))
+        ));
+    }
+}
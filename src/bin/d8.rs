@@ -1,187 +1,169 @@
 #![allow(dead_code)]
 
 use itertools::Itertools;
-use std::collections::{HashMap, HashSet};
-
-use utils::{AsciiByte, BasicGrid, Coord};
-
-fn add_nodes_part1(pair: &[&Coord], width: usize, height: usize, nodes: &mut HashSet<Coord>) {
-    assert!(pair.len() == 2);
-
-    let wi = std::convert::TryInto::<i64>::try_into(width).unwrap();
-    let hi = std::convert::TryInto::<i64>::try_into(height).unwrap();
-
-    let px0 = std::convert::TryInto::<i64>::try_into(pair[0].col).unwrap();
-    let px1 = std::convert::TryInto::<i64>::try_into(pair[1].col).unwrap();
-    let py0 = std::convert::TryInto::<i64>::try_into(pair[0].row).unwrap();
-    let py1 = std::convert::TryInto::<i64>::try_into(pair[1].row).unwrap();
-
-    let dx = px0 - px1;
-    let dy = py0 - py1;
-
-    if px0 + dx >= 0 && px0 + dx < wi && py0 + dy >= 0 && py0 + dy < hi {
-        nodes.insert(Coord::new((py0 + dy) as usize, (px0 + dx) as usize));
-    }
-
-    let dx = px1 - px0;
-    let dy = py1 - py0;
-
-    if px1 + dx >= 0 && px1 + dx < wi && py1 + dy >= 0 && py1 + dy < hi {
-        nodes.insert(Coord::new((py1 + dy) as usize, (px1 + dx) as usize));
-    }
+use owo_colors::OwoColorize;
+use std::collections::HashMap;
+
+use utils::{AsciiByte, BasicGrid, BitGrid, Coord};
+
+/// What one step outward from an antenna means.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Unit {
+    /// the full offset between the two antennas
+    Separation,
+    /// the smallest grid step along the line through both antennas
+    Lattice,
 }
-fn divisors(n: i64) -> Vec<i64> {
-    let mut ret = vec![];
-    let mut i = 1;
-    while i <= n.abs() / 2 {
-        if n % i == 0 {
-            ret.push(i * n.signum());
-        }
-        i += 1;
-    }
-    ret
+
+/// Which points on the line through a pair of antennas are antinodes.
+/// Harmonic `k` is `k` units beyond an antenna, away from its partner;
+/// harmonic 0 is the antenna itself.
+#[derive(Clone, Copy, Debug)]
+struct AntinodeRules {
+    unit: Unit,
+    from: usize,
+    /// last harmonic, or `None` to run to the edge of the map
+    to: Option<usize>,
+    /// also count grid points strictly between the two antennas
+    between: bool,
 }
 
-fn fill_part2(
-    nodes: &mut HashSet<Coord>,
-    width: usize,
-    height: usize,
-    start: Coord,
-    dx: isize,
-    dy: isize,
-) {
-    let mut c = start;
-    while c.col < width && c.row < height {
-        nodes.insert(c);
-        let (v, of) = c.col.overflowing_add_signed(dx);
-        if of {
-            break;
-        } else {
-            c.col = v;
-        }
-        let (v, of) = c.row.overflowing_add_signed(dy);
-        if of {
-            break;
-        } else {
-            c.row = v;
+const PART1: AntinodeRules = AntinodeRules {
+    unit: Unit::Separation,
+    from: 1,
+    to: Some(1),
+    between: false,
+};
+
+const PART2: AntinodeRules = AntinodeRules {
+    unit: Unit::Lattice,
+    from: 0,
+    to: None,
+    between: true,
+};
+
+fn add_antinodes(a: Coord, b: Coord, rules: &AntinodeRules, nodes: &mut BitGrid) {
+    let (w, h) = (nodes.width, nodes.height);
+    let on_map = |c: &Coord| c.row < h && c.col < w;
+    let d = a - b;
+    let step = match rules.unit {
+        Unit::Separation => d,
+        Unit::Lattice => d.primitive(),
+    };
+    for (origin, out) in [(a, step), (b, -step)] {
+        let mut k = rules.from;
+        while rules.to.is_none_or(|to| k <= to) {
+            match origin.offset(out * k as isize).filter(on_map) {
+                Some(c) => nodes.set(c),
+                None => break,
+            };
+            k += 1;
         }
     }
-    let mut c = start;
-    while c.col < width && c.row < height {
-        nodes.insert(c);
-        let (v, of) = c.col.overflowing_add_signed(-dx);
-        if of {
-            break;
-        } else {
-            c.col = v;
-        }
-        let (v, of) = c.row.overflowing_add_signed(-dy);
-        if of {
-            break;
-        } else {
-            c.row = v;
+    if rules.between {
+        let s = d.primitive();
+        let mut c = b.offset(s).unwrap();
+        while c != a {
+            nodes.set(c);
+            c = c.offset(s).unwrap();
         }
     }
 }
 
-fn add_nodes_part2(pair: &[&Coord], width: usize, height: usize, nodes: &mut HashSet<Coord>) {
-    assert!(pair.len() == 2);
-
-    let x_diff = pair[1].col as isize - pair[0].col as isize;
-    let y_diff = pair[1].row as isize - pair[0].row as isize;
-
-    let (dx, dy) = if x_diff == 0 {
-        (0_isize, y_diff / y_diff.abs())
-    } else if y_diff == 0 {
-        (x_diff / x_diff.abs(), 0_isize)
-    } else {
-        (1..=x_diff.abs().min(y_diff.abs()))
-            .rev()
-            //            .inspect(|n| println!("n: {}, x_diff: {}, y_diff: {}", n, x_diff, y_diff))
-            .find(|n| x_diff % n == 0 && y_diff % n == 0)
-            .map(|n| (x_diff / n, y_diff / n))
-            .unwrap()
-        /*
-            let col_divs = divisors(x_diff);
-        let row_divs = divisors(y_diff);
-
-        col_divs
-            .iter()
-            .copied()
-            .cartesian_product(row_divs.iter().copied())
-            .map(|(x, y)| (x as i64, y as i64))
-            .find(|(dx, dy)| {
-                if x_diff % dx == 0 && y_diff % dy == 0 {
-                    println!(
-                        "testing ({},{}) on pair {:?}\t{:?}",
-                        dx, dy, pair[0], pair[1]
-                    );
-                    println!("xdiff: {}, ydiff: {}", x_diff, y_diff);
-                    (1..x_diff.abs()).any(|n| n * dx == x_diff && n * dy == y_diff)
-                } else {
-                    false
-                }
-            })
-            .unwrap()
-        */
-    };
-    fill_part2(nodes, width, height, *pair[0], dx, dy);
-    /*
-    println!(
-        "for pair ({:?}, {:?}):\t\tdx = {}\tdy = {}",
-        pair[0], pair[1], dx, dy
-    );
-        */
+/// Antinodes for each frequency, and all of them together.
+struct Antinodes {
+    per_freq: Vec<(AsciiByte, BitGrid)>,
+    all: BitGrid,
 }
 
-fn part1(grid: BasicGrid<AsciiByte>, node_map: HashMap<AsciiByte, Vec<Coord>>) {
-    let mut ans: HashSet<Coord> = Default::default();
-    for (_, coords) in node_map.iter() {
-        coords.iter().combinations(2).for_each(|pair| {
-            add_nodes_part1(&pair, grid.width, grid.height, &mut ans);
-        });
-    }
-    for r in 0..grid.height {
-        for c in 0..grid.width {
-            let coord = Coord::new(r, c);
-            print!(
-                "{}",
-                if ans.contains(&coord) {
-                    AsciiByte(b'#')
-                } else {
-                    grid[coord]
-                }
-            );
-        }
-        println!();
-    }
+fn antinodes(
+    grid: &BasicGrid<AsciiByte>,
+    node_map: &HashMap<AsciiByte, Vec<Coord>>,
+    rules: &AntinodeRules,
+) -> Antinodes {
+    let mut all = BitGrid::for_grid(grid);
+    let per_freq: Vec<(AsciiByte, BitGrid)> = node_map
+        .iter()
+        .sorted_by_key(|(freq, _)| freq.0)
+        .map(|(freq, coords)| {
+            let mut nodes = BitGrid::for_grid(grid);
+            for pair in coords.iter().combinations(2) {
+                add_antinodes(*pair[0], *pair[1], rules, &mut nodes);
+            }
+            all.union_with(&nodes);
+            (*freq, nodes)
+        })
+        .collect();
+    Antinodes { per_freq, all }
+}
 
-    println!("{}", ans.len());
+/// A distinct, stable color for each frequency.
+fn freq_color(freq: AsciiByte) -> (u8, u8, u8) {
+    let hue = (freq.0 as f64 * 0.618_033_988_75).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u8 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let scale = |v: f64| (80.0 + v * 175.0) as u8;
+    (scale(r), scale(g), scale(b))
 }
 
-fn part2(grid: BasicGrid<AsciiByte>, node_map: HashMap<AsciiByte, Vec<Coord>>) {
-    let mut ans: HashSet<Coord> = Default::default();
-    for (_, coords) in node_map.iter() {
-        coords.iter().combinations(2).for_each(|pair| {
-            add_nodes_part2(&pair, grid.width, grid.height, &mut ans);
-        });
-    }
+/// Antennas and antinodes in their frequency's color; `*` marks antinodes
+/// shared by more than one frequency.
+fn render(grid: &BasicGrid<AsciiByte>, nodes: &Antinodes) {
+    let freq_idx: HashMap<AsciiByte, usize> = nodes
+        .per_freq
+        .iter()
+        .enumerate()
+        .map(|(i, (f, _))| (*f, i))
+        .collect();
     for r in 0..grid.height {
         for c in 0..grid.width {
             let coord = Coord::new(r, c);
-            print!(
-                "{}",
-                if ans.contains(&coord) {
-                    AsciiByte(b'#')
-                } else {
-                    grid[coord]
-                }
-            );
+            let owners: Vec<AsciiByte> = nodes
+                .per_freq
+                .iter()
+                .filter(|(_, n)| n.test(coord))
+                .map(|(f, _)| *f)
+                .collect();
+            if let Some(i) = freq_idx.get(&grid[coord]) {
+                let (red, green, blue) = freq_color(nodes.per_freq[*i].0);
+                print!("{}", grid[coord].truecolor(red, green, blue).bold());
+            } else if owners.len() > 1 {
+                print!("{}", "*".bold());
+            } else if let Some(f) = owners.first() {
+                let (red, green, blue) = freq_color(*f);
+                print!("{}", "#".truecolor(red, green, blue));
+            } else {
+                print!("{}", grid[coord]);
+            }
         }
         println!();
     }
+}
 
-    println!("{}", ans.len());
+fn run(
+    grid: &BasicGrid<AsciiByte>,
+    node_map: &HashMap<AsciiByte, Vec<Coord>>,
+    rules: &AntinodeRules,
+) {
+    let nodes = antinodes(grid, node_map, rules);
+    render(grid, &nodes);
+    for (freq, n) in nodes.per_freq.iter() {
+        let (red, green, blue) = freq_color(*freq);
+        println!(
+            "{}: {} antennas, {} antinodes",
+            freq.truecolor(red, green, blue),
+            node_map[freq].len(),
+            n.count()
+        );
+    }
+    println!("{}", nodes.all.count());
 }
 
 fn main() {
@@ -198,7 +180,8 @@ fn main() {
             node_map.entry(grid[c]).or_default().push(c);
         }
     }
-    part2(grid, node_map);
+    run(&grid, &node_map, &PART2);
+    //run(&grid, &node_map, &PART1);
 }
 
 static TEST: &str = "............
@@ -219,13 +202,44 @@ mod test {
 
     use super::*;
 
-    #[test]
-    fn test_divisors() {
-        assert_eq!(divisors(6), vec![1, 2, 3]);
-        assert_eq!(divisors(12), vec![1, 2, 3, 4, 6]);
-        assert_eq!(divisors(13), vec![1]);
-        assert_eq!(divisors(0), vec![]);
+    fn test_input() -> (BasicGrid<AsciiByte>, HashMap<AsciiByte, Vec<Coord>>) {
+        let input: Vec<&str> = TEST.lines().collect();
+        let grid: BasicGrid<AsciiByte> = BasicGrid::new(&input);
+        let mut node_map: HashMap<AsciiByte, Vec<Coord>> = Default::default();
+        for c in grid.row_major_iter() {
+            if grid[c] != b'.'.into() {
+                node_map.entry(grid[c]).or_default().push(c);
+            }
+        }
+        (grid, node_map)
+    }
 
-        assert_eq!(divisors(-12), vec![-1, -2, -3, -4, -6]);
+    #[test]
+    fn test_antinodes() {
+        let (grid, node_map) = test_input();
+        let part1 = antinodes(&grid, &node_map, &PART1);
+        assert_eq!(part1.all.count(), 14);
+        assert_eq!(part1.per_freq.len(), 2);
+        let part2 = antinodes(&grid, &node_map, &PART2);
+        assert_eq!(part2.all.count(), 34);
+
+        // a pair two lattice steps apart has one point between them
+        let mut nodes = BitGrid::new(10, 10);
+        let rules = AntinodeRules {
+            unit: Unit::Separation,
+            from: 1,
+            to: Some(2),
+            between: true,
+        };
+        add_antinodes(Coord::new(4, 4), Coord::new(6, 6), &rules, &mut nodes);
+        assert_eq!(
+            nodes.iter().collect::<Vec<_>>(),
+            vec![
+                Coord::new(0, 0),
+                Coord::new(2, 2),
+                Coord::new(5, 5),
+                Coord::new(8, 8)
+            ]
+        );
     }
 }
@@ -1,212 +1,551 @@
 use std::{
-    cmp::Reverse,
-    collections::{BinaryHeap, HashMap, VecDeque},
-    fs::File,
+    collections::{BTreeMap, BTreeSet, HashMap},
     io::Write,
 };
 
-use random_color::RandomColor;
+type FileId = u32;
 
-fn part1(input: &str, mut data: Vec<u16>) {
-    let mut in_skip = false;
-    let mut next_id: u16 = 0;
-    for b in input.trim().as_bytes() {
-        if in_skip {
-            let n = *b - 48;
-            for i in 0..n {
-                data.push(u16::MAX);
-            }
-        } else {
-            let n = *b - 48;
-            for i in 0..n {
-                data.push(next_id);
+/// A run of `len` blocks starting at `start` that all belong to one file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Extent {
+    id: FileId,
+    start: u64,
+    len: u64,
+}
+
+impl Extent {
+    fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    /// Sum of position * id over the extent's blocks.
+    fn checksum(&self) -> u128 {
+        let (start, len) = (self.start as u128, self.len as u128);
+        self.id as u128 * (start * len + len * (len.saturating_sub(1)) / 2)
+    }
+}
+
+/// `len` blocks of file `id` moved from `from` to `to`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Move {
+    id: FileId,
+    from: u64,
+    to: u64,
+    len: u64,
+}
+
+/// Free blocks as disjoint, non-adjacent `start -> len` intervals, also
+/// indexed by length so a fitting span can be found without a scan.
+#[derive(Clone, Default)]
+struct FreeSpace {
+    by_start: BTreeMap<u64, u64>,
+    by_len: BTreeMap<u64, BTreeSet<u64>>,
+}
+
+impl FreeSpace {
+    fn insert(&mut self, start: u64, len: u64) {
+        self.by_start.insert(start, len);
+        self.by_len.entry(len).or_default().insert(start);
+    }
+
+    fn remove(&mut self, start: u64) -> u64 {
+        let len = self.by_start.remove(&start).unwrap();
+        let starts = self.by_len.get_mut(&len).unwrap();
+        starts.remove(&start);
+        if starts.is_empty() {
+            self.by_len.remove(&len);
+        }
+        len
+    }
+
+    /// Marks `start..start + len` free, merging it with its neighbours.
+    fn release(&mut self, mut start: u64, mut len: u64) {
+        if len == 0 {
+            return;
+        }
+        if let Some((&s, &l)) = self.by_start.range(..start).next_back() {
+            if s + l == start {
+                self.remove(s);
+                start = s;
+                len += l;
             }
-            next_id += 1;
         }
-        in_skip = !in_skip;
+        if self.by_start.contains_key(&(start + len)) {
+            len += self.remove(start + len);
+        }
+        self.insert(start, len);
     }
-    let mut first_free = data
-        .iter()
-        .enumerate()
-        .find_map(|(n, b)| if *b == u16::MAX { Some(n) } else { None })
-        .unwrap();
-    let mut last_used = data.len() - 1;
-    while first_free < last_used {
-        data.swap(first_free, last_used);
-        first_free += 1;
-        while data[first_free] != u16::MAX {
-            first_free += 1;
-        }
-        last_used -= 1;
-        while data[last_used] == u16::MAX {
-            last_used -= 1;
-        }
-    }
-    let v: u64 = data.iter().enumerate().fold(0, |acc, (n, c)| {
-        if *c == u16::MAX {
-            acc
-        } else {
-            acc + n as u64 * *c as u64
-        }
-    });
-    println!("{}", v);
-}
-
-struct Part2 {
-    free_maps: [BinaryHeap<Reverse<usize>>; 9],
-    data: Vec<u16>,
-}
-
-impl Part2 {
-    fn new(input: &str, mut data: Vec<u16>) -> Self {
-        let mut in_skip = false;
-        let mut next_id: u16 = 0;
-        let mut free_maps: [BinaryHeap<Reverse<usize>>; 9] = Default::default();
-
-        for b in input.trim().as_bytes() {
-            if in_skip {
-                let n = (*b - 48) as usize;
-                if n > 0 {
-                    let start = data.len();
-                    for i in 0..n {
-                        data.push(u16::MAX);
-                    }
-                    free_maps[n - 1].push(Reverse(start));
-                }
-            } else {
-                let n = *b - 48;
-                assert!(n > 0);
-                for i in 0..n {
-                    data.push(next_id);
-                }
-                next_id += 1;
+
+    /// Allocates the first `n` blocks of the span at `start`.
+    fn take(&mut self, start: u64, n: u64) {
+        let len = self.remove(start);
+        assert!(n <= len, "taking {n} blocks from a span of {len}");
+        if len > n {
+            self.insert(start + n, len - n);
+        }
+    }
+
+    fn first(&self) -> Option<(u64, u64)> {
+        self.by_start.first_key_value().map(|(s, l)| (*s, *l))
+    }
+
+    fn spans(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.by_start.iter().map(|(s, l)| (*s, *l))
+    }
+
+    fn largest(&self) -> u64 {
+        self.by_len.last_key_value().map_or(0, |(l, _)| *l)
+    }
+
+    /// Start of the span chosen by `fit` among those holding at least `len`
+    /// blocks and starting before `before`.
+    fn find(&self, len: u64, before: u64, fit: Fit) -> Option<u64> {
+        let leftmost = |starts: &BTreeSet<u64>| starts.first().copied().filter(|s| *s < before);
+        let mut sizes = self.by_len.range(len..);
+        match fit {
+            Fit::First => sizes.filter_map(|(_, starts)| leftmost(starts)).min(),
+            Fit::Best => sizes.find_map(|(_, starts)| leftmost(starts)),
+            Fit::Worst => sizes.rev().find_map(|(_, starts)| leftmost(starts)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Disk {
+    extents: Vec<Extent>,
+    free: FreeSpace,
+    /// Every move so far, each onto blocks that were free at the time.
+    log: Vec<Move>,
+}
+
+impl Disk {
+    fn new(input: &str) -> Self {
+        let mut extents: Vec<Extent> = vec![];
+        let mut free = FreeSpace::default();
+        let mut pos = 0;
+        for (i, b) in input.trim().bytes().enumerate() {
+            let len = (b - b'0') as u64;
+            if i % 2 == 1 {
+                free.release(pos, len);
+            } else if len > 0 {
+                let id = FileId::try_from(i / 2).expect("too many files");
+                extents.push(Extent {
+                    id,
+                    start: pos,
+                    len,
+                });
             }
-            in_skip = !in_skip;
+            pos += len;
+        }
+        Self {
+            extents,
+            free,
+            log: vec![],
         }
-        println!("data len: {}", data.len());
-        Self { free_maps, data }
     }
 
-    fn first_fit(&mut self, src: usize, len: usize) -> Option<usize> {
-        let mut ret = None;
-        for i in (len - 1)..self.free_maps.len() {
-            if let Some(p) = self.free_maps[i].peek() {
-                if p.0 < src && ret.is_none_or(|(idx, old_p)| p.0 < old_p) {
-                    ret = Some((i, p.0));
-                }
+    fn checksum(&self) -> u128 {
+        self.extents.iter().map(Extent::checksum).sum()
+    }
+
+    /// Sorts extents by position and joins touching pieces of the same file.
+    fn normalize(&mut self) {
+        self.extents.sort_by_key(|e| e.start);
+        let mut merged: Vec<Extent> = Vec::with_capacity(self.extents.len());
+        for e in self.extents.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.id == e.id && last.end() == e.start => last.len += e.len,
+                _ => merged.push(e),
             }
         }
-        ret.map(|(idx, p)| idx)
+        self.extents = merged;
+    }
+
+    /// The extents of each file, in position order.
+    fn files(&self) -> HashMap<FileId, Vec<(u64, u64)>> {
+        let mut files: HashMap<FileId, Vec<(u64, u64)>> = Default::default();
+        for e in self.extents.iter() {
+            files.entry(e.id).or_default().push((e.start, e.len));
+        }
+        files.values_mut().for_each(|v| v.sort());
+        files
     }
 
-    fn move_data(&mut self, idx: usize, src: usize, l: usize) -> usize {
-        let dst = self.free_maps[idx].pop().unwrap();
-        self.data.copy_within(src..(src + l), dst.0);
-        self.data[src..(src + l)].fill(u16::MAX);
-        dst.0
+    /// One past the last block, used or free.
+    fn size(&self) -> u64 {
+        let end = self.extents.iter().map(Extent::end).max().unwrap_or(0);
+        self.free.spans().map(|(s, l)| s + l).fold(end, u64::max)
+    }
+
+    /// Every block in order, `None` for free ones. Only for small disks.
+    fn blocks(&self) -> Vec<Option<FileId>> {
+        let mut blocks = vec![None; self.size() as usize];
+        for e in self.extents.iter() {
+            blocks[e.start as usize..e.end() as usize].fill(Some(e.id));
+        }
+        blocks
     }
 
     fn dump_data(&self) {
-        for i in 0..self.data.len() {
-            if self.data[i] == u16::MAX {
-                print!(".");
-            } else {
-                print!("{}", self.data[i]);
+        for b in self.blocks() {
+            match b {
+                None => print!("."),
+                Some(id) => print!("{}", id),
             }
         }
         println!();
     }
+}
+
+static ANIMATION: &str = include_str!("d9.html");
+
+/// Writes a standalone page that replays `strategy` on `disk` one move at a
+/// time. The disk is embedded as its extents plus the move log, and drawn
+/// on a canvas, so large inputs stay small.
+fn write_animation<W>(disk: &Disk, strategy: &dyn Strategy, mut w: W) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let (after, _) = run(disk, strategy);
+    let extents: Vec<String> = disk
+        .extents
+        .iter()
+        .map(|e| format!("[{},{},{}]", e.id, e.start, e.len))
+        .collect();
+    let moves: Vec<String> = after
+        .log
+        .iter()
+        .map(|m| format!("[{},{},{},{}]", m.id, m.from, m.to, m.len))
+        .collect();
+    let data = format!(
+        "{{\"strategy\":\"{}\",\"size\":{},\"extents\":[{}],\"moves\":[{}]}}",
+        strategy.name(),
+        disk.size(),
+        extents.join(","),
+        moves.join(",")
+    );
+    w.write_all(ANIMATION.replace("/*DATA*/null", &data).as_bytes())
+}
+
+trait Strategy {
+    fn name(&self) -> &'static str;
+
+    fn compact(&self, disk: &mut Disk);
+}
+
+/// Moves single blocks from the end into the first free block (part 1).
+struct BlockLevel;
+
+/// Moves whole files, highest id first, each at most once.
+struct WholeFile(Fit);
+
+/// Block-level compaction, then reorders the packed blocks so every file is
+/// contiguous again. The reordering swaps single blocks through a free one,
+/// so each step is logged like any other move.
+struct CompactThenDefragment;
+
+#[derive(Clone, Copy, Debug)]
+enum Fit {
+    /// leftmost span that fits (part 2)
+    First,
+    /// smallest span that fits
+    Best,
+    /// largest span that fits
+    Worst,
+}
+
+impl Strategy for BlockLevel {
+    fn name(&self) -> &'static str {
+        "block-level"
+    }
 
-    fn dump_data_html<W>(&self, mut w: W) -> std::io::Result<()>
-    where
-        W: Write,
-    {
-        let colors: [String; 8] = core::array::from_fn(|i| RandomColor::new().to_hex());
-        write!(&mut w, "<!DOCTYPE html>")?;
-        writeln!(
-            &mut w,
-            "<html><head><link rel=\"stylesheet\" href=\"d9.css\"><title>Day 9 testing</title></head><body><table>"
-        )?;
-        for r in self.data.chunks(100) {
-            writeln!(&mut w, "<tr>")?;
-            for c in r {
-                if *c == u16::MAX {
-                    write!(&mut w, "<td class=\"empty\">.</td>")?
-                } else {
-                    write!(
-                        &mut w,
-                        "<td class=\"file color-{}\" title=\"{}\">{}</td>",
-                        c % 8,
-                        c,
-                        c % 100
-                    )?;
+    fn compact(&self, disk: &mut Disk) {
+        disk.normalize();
+        let mut placed: Vec<Extent> = Vec::with_capacity(disk.extents.len());
+        while let Some(mut last) = disk.extents.pop() {
+            while let Some((start, len)) = disk.free.first().filter(|(s, _)| *s < last.start) {
+                let n = len.min(last.len);
+                disk.free.take(start, n);
+                disk.free.release(last.end() - n, n);
+                placed.push(Extent {
+                    start,
+                    len: n,
+                    ..last
+                });
+                disk.log.push(Move {
+                    id: last.id,
+                    from: last.end() - n,
+                    to: start,
+                    len: n,
+                });
+                last.len -= n;
+                if last.len == 0 {
+                    break;
                 }
             }
-            writeln!(&mut w, "</tr>")?;
+            if last.len > 0 {
+                // no gaps before this file, so nothing left of it moves either
+                placed.push(last);
+                placed.append(&mut disk.extents);
+            }
         }
-        writeln!(&mut w, "</table></body></html>")?;
-
-        Ok(())
+        disk.extents = placed;
+        disk.normalize();
     }
 }
 
-fn part2(input: &str, mut data: Vec<u16>) {
-    let mut map = Part2::new(input, data);
-    let mut scan = map.data.len() - 1;
-    let mut rep = 100;
-
-    while scan > 0 {
-        if rep < 100 {
-            let f = File::create(format!("debug/day9-output-{rep}.html")).unwrap();
-            map.dump_data_html(f);
-            rep += 1;
+impl Strategy for WholeFile {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            Fit::First => "first-fit",
+            Fit::Best => "best-fit",
+            Fit::Worst => "worst-fit",
         }
+    }
 
-        let mut l = 1;
-        while scan > 0 && map.data[scan] == map.data[scan - 1] {
-            l += 1;
-            if scan > 0 {
-                scan -= 1;
-            } else {
-                break;
+    fn compact(&self, disk: &mut Disk) {
+        disk.extents
+            .sort_by_key(|e| std::cmp::Reverse((e.id, e.start)));
+        for e in disk.extents.iter_mut() {
+            if let Some(dst) = disk.free.find(e.len, e.start, self.0) {
+                disk.free.take(dst, e.len);
+                disk.free.release(e.start, e.len);
+                disk.log.push(Move {
+                    id: e.id,
+                    from: e.start,
+                    to: dst,
+                    len: e.len,
+                });
+                e.start = dst;
             }
         }
-        if let Some(idx) = map.first_fit(scan, l) {
-            println!("moving file {} ({l} chars) to idx {idx}", map.data[scan]);
-            let dst = map.move_data(idx, scan, l);
-            if idx >= l {
-                map.free_maps[idx - l].push(Reverse(dst + l));
+        disk.normalize();
+    }
+}
+
+impl Strategy for CompactThenDefragment {
+    fn name(&self) -> &'static str {
+        "compact+defrag"
+    }
+
+    fn compact(&self, disk: &mut Disk) {
+        BlockLevel.compact(disk);
+        // everything is packed from block 0, so files can be laid out again
+        // in order of their first extent
+        let mut order: Vec<FileId> = vec![];
+        let mut sizes: HashMap<FileId, u64> = Default::default();
+        for e in disk.extents.iter() {
+            *sizes.entry(e.id).or_insert_with(|| {
+                order.push(e.id);
+                0
+            }) += e.len;
+        }
+        let mut targets: HashMap<FileId, u64> = Default::default();
+        let mut start = 0;
+        for id in order.iter() {
+            targets.insert(*id, start);
+            start += sizes[id];
+        }
+        // where each packed block belongs, and which block belongs at each
+        // position
+        let mut owner: Vec<FileId> = vec![0; start as usize];
+        let mut dest: Vec<usize> = vec![0; start as usize];
+        let mut src: Vec<usize> = vec![0; start as usize];
+        for e in disk.extents.iter() {
+            let target = targets.get_mut(&e.id).unwrap();
+            for b in e.start..e.end() {
+                owner[b as usize] = e.id;
+                dest[b as usize] = *target as usize;
+                src[*target as usize] = b as usize;
+                *target += 1;
             }
         }
-        while scan > 0 {
-            scan -= 1;
-            if map.data[scan] != u16::MAX {
-                break;
+        let mut relocate = |id: FileId, from: usize, to: usize| {
+            disk.log.push(Move {
+                id,
+                from: from as u64,
+                to: to as u64,
+                len: 1,
+            })
+        };
+        for first in 0..dest.len() {
+            if dest[first] == first {
+                continue;
             }
+            // park the cycle's first block, pull each block into the hole
+            // left by the previous one, then drop the parked block last
+            let spare = disk.free.first().expect("a block moved, so one is free").0 as usize;
+            relocate(owner[first], first, spare);
+            let mut hole = first;
+            while src[hole] != first {
+                relocate(owner[src[hole]], src[hole], hole);
+                dest[src[hole]] = src[hole];
+                hole = src[hole];
+            }
+            relocate(owner[first], spare, hole);
+            dest[first] = first;
         }
+        let mut start = 0;
+        disk.extents = order
+            .into_iter()
+            .map(|id| {
+                let e = Extent {
+                    id,
+                    start,
+                    len: sizes[&id],
+                };
+                start += e.len;
+                e
+            })
+            .collect();
     }
+}
 
-    let v: u64 = map.data.iter().enumerate().fold(0, |acc, (n, c)| {
-        if *c == u16::MAX {
-            acc
-        } else {
-            acc + n as u64 * *c as u64
-        }
-    });
-    println!("{}", v);
+#[derive(Debug, PartialEq, Eq)]
+struct Report {
+    checksum: u128,
+    moved_files: usize,
+    /// free spans with file blocks after them
+    holes: usize,
+    /// files split over more than one extent
+    fragmented_files: usize,
+    largest_free_span: u64,
+}
+
+fn run(disk: &Disk, strategy: &dyn Strategy) -> (Disk, Report) {
+    let mut after = disk.clone();
+    strategy.compact(&mut after);
+
+    let before_files = disk.files();
+    let after_files = after.files();
+    let used_end = after.extents.iter().map(Extent::end).max().unwrap_or(0);
+    let report = Report {
+        checksum: after.checksum(),
+        moved_files: before_files
+            .iter()
+            .filter(|(id, extents)| after_files.get(id) != Some(extents))
+            .count(),
+        holes: after.free.spans().filter(|(s, _)| *s < used_end).count(),
+        fragmented_files: after_files.values().filter(|e| e.len() > 1).count(),
+        largest_free_span: after.free.largest(),
+    };
+    (after, report)
+}
+
+fn compare(disk: &Disk) {
+    let strategies: [&dyn Strategy; 5] = [
+        &BlockLevel,
+        &WholeFile(Fit::First),
+        &WholeFile(Fit::Best),
+        &WholeFile(Fit::Worst),
+        &CompactThenDefragment,
+    ];
+    println!(
+        "{:<16} {:>16} {:>8} {:>8} {:>12} {:>12}",
+        "strategy", "checksum", "moved", "holes", "fragmented", "largest free"
+    );
+    for s in strategies {
+        let (_, r) = run(disk, s);
+        println!(
+            "{:<16} {:>16} {:>8} {:>8} {:>12} {:>12}",
+            s.name(),
+            r.checksum,
+            r.moved_files,
+            r.holes,
+            r.fragmented_files,
+            r.largest_free_span
+        );
+    }
 }
 
 fn main() {
     let input = std::fs::read_to_string("input/d9.txt").unwrap();
     //let input = TEST;
-    let mut v: u64 = 0;
-    for b in input.trim().as_bytes() {
-        v += (*b - 48) as u64;
-    }
-    let mut data: Vec<u16> = Vec::with_capacity(100000);
-    part2(&input, data);
+    let disk = Disk::new(&input);
+    compare(&disk);
+    //write_animation(&disk, &WholeFile(Fit::First), std::fs::File::create("d9.html").unwrap()).unwrap();
 }
 
 //static TEST: &str = "12345";
 static TEST: &str = "2333133121414131402";
 //static TEST: &str = "35904175134";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strategies() {
+        let disk = Disk::new(TEST);
+        let (after, r) = run(&disk, &BlockLevel);
+        assert_eq!(r.checksum, 1928);
+        assert_eq!(r.holes, 0);
+        assert_eq!(r.largest_free_span, 14);
+        let prefix = [0, 0, 9, 9, 8, 1, 1, 1, 8, 8, 8, 2];
+        assert_eq!(after.blocks()[..12], prefix.map(Some));
+
+        let (_, r) = run(&disk, &WholeFile(Fit::First));
+        assert_eq!(r.checksum, 2858);
+        assert_eq!(r.moved_files, 4);
+        assert_eq!(r.fragmented_files, 0);
+
+        let (after, r) = run(&disk, &CompactThenDefragment);
+        assert_eq!(r.holes, 0);
+        assert_eq!(r.fragmented_files, 0);
+        let prefix = [0, 0, 9, 9, 8, 8, 8, 8, 1, 1, 1];
+        assert_eq!(after.blocks()[..11], prefix.map(Some));
+    }
+
+    #[test]
+    fn test_log_replays() {
+        let disk = Disk::new(TEST);
+        for strategy in [
+            &BlockLevel as &dyn Strategy,
+            &WholeFile(Fit::Best),
+            &CompactThenDefragment,
+        ] {
+            let (after, _) = run(&disk, strategy);
+            let mut blocks = disk.blocks();
+            for m in after.log.iter() {
+                let (from, to, len) = (m.from as usize, m.to as usize, m.len as usize);
+                assert!(blocks[to..to + len].iter().all(Option::is_none));
+                assert!(blocks[from..from + len].iter().all(|b| *b == Some(m.id)));
+                blocks[from..from + len].fill(None);
+                blocks[to..to + len].fill(Some(m.id));
+            }
+            assert_eq!(blocks, after.blocks());
+        }
+
+        let mut html: Vec<u8> = vec![];
+        write_animation(&disk, &WholeFile(Fit::First), &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("\"moves\":[[9,40,2,2],"));
+        assert!(!html.contains("/*DATA*/"));
+    }
+
+    #[test]
+    fn test_many_files() {
+        // more files than a u16 id can hold, checked against a block-by-block
+        // compaction
+        let input = "9192".repeat(40_000);
+        let disk = Disk::new(&input);
+        let mut blocks = disk.blocks();
+        let (mut i, mut j) = (0, blocks.len() - 1);
+        while i < j {
+            if blocks[i].is_some() {
+                i += 1;
+            } else if blocks[j].is_none() {
+                j -= 1;
+            } else {
+                blocks.swap(i, j);
+            }
+        }
+        let expected: u128 = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(n, b)| b.map(|id| n as u128 * id as u128))
+            .sum();
+        assert_eq!(run(&disk, &BlockLevel).1.checksum, expected);
+        assert_eq!(run(&disk, &WholeFile(Fit::First)).1.moved_files, 0);
+    }
+}
@@ -0,0 +1,351 @@
+use std::fmt::Display;
+
+use crate::{AsciiByte, BasicGrid, Coord, Dir};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Bits {
+    words: Box<[u64]>,
+    len: usize,
+}
+
+impl Bits {
+    fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(WORD_BITS)].into_boxed_slice(),
+            len,
+        }
+    }
+
+    fn locate(&self, i: usize) -> (usize, u64) {
+        assert!(i < self.len, "bit {} out of range ({})", i, self.len);
+        (i / WORD_BITS, 1 << (i % WORD_BITS))
+    }
+
+    fn set(&mut self, i: usize) -> bool {
+        let (w, mask) = self.locate(i);
+        let was = self.words[w] & mask != 0;
+        self.words[w] |= mask;
+        !was
+    }
+
+    fn clear(&mut self, i: usize) -> bool {
+        let (w, mask) = self.locate(i);
+        let was = self.words[w] & mask != 0;
+        self.words[w] &= !mask;
+        was
+    }
+
+    fn test(&self, i: usize) -> bool {
+        let (w, mask) = self.locate(i);
+        self.words[w] & mask != 0
+    }
+
+    fn count(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    fn reset(&mut self) {
+        self.words.fill(0);
+    }
+
+    fn combine<F: Fn(u64, u64) -> u64>(&mut self, other: &Bits, f: F) {
+        assert_eq!(self.len, other.len, "bit grids differ in size");
+        for (a, b) in self.words.iter_mut().zip(other.words.iter()) {
+            *a = f(*a, *b);
+        }
+    }
+
+    fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(w, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                (word != 0).then(|| {
+                    let bit = word.trailing_zeros() as usize;
+                    word &= word - 1;
+                    w * WORD_BITS + bit
+                })
+            })
+        })
+    }
+}
+
+/// A packed set of grid cells, one bit per cell.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BitGrid {
+    bits: Bits,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl BitGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            bits: Bits::new(width * height),
+            width,
+            height,
+        }
+    }
+
+    pub fn for_grid<T>(grid: &BasicGrid<T>) -> Self {
+        Self::new(grid.width, grid.height)
+    }
+
+    fn idx(&self, pos: Coord) -> usize {
+        assert!(pos.col < self.width, "{:?} out of range", pos);
+        pos.row * self.width + pos.col
+    }
+
+    /// Marks `pos`, returning `true` if it was not already set.
+    pub fn set(&mut self, pos: Coord) -> bool {
+        let i = self.idx(pos);
+        self.bits.set(i)
+    }
+
+    /// Unmarks `pos`, returning `true` if it had been set.
+    pub fn clear(&mut self, pos: Coord) -> bool {
+        let i = self.idx(pos);
+        self.bits.clear(i)
+    }
+
+    pub fn test(&self, pos: Coord) -> bool {
+        self.bits.test(self.idx(pos))
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.words.iter().all(|w| *w == 0)
+    }
+
+    /// Clears every cell without reallocating.
+    pub fn reset(&mut self) {
+        self.bits.reset();
+    }
+
+    pub fn union_with(&mut self, other: &BitGrid) {
+        self.bits.combine(&other.bits, |a, b| a | b);
+    }
+
+    pub fn intersect_with(&mut self, other: &BitGrid) {
+        self.bits.combine(&other.bits, |a, b| a & b);
+    }
+
+    pub fn difference_with(&mut self, other: &BitGrid) {
+        self.bits.combine(&other.bits, |a, b| a & !b);
+    }
+
+    pub fn union(&self, other: &BitGrid) -> BitGrid {
+        let mut g = self.clone();
+        g.union_with(other);
+        g
+    }
+
+    pub fn intersection(&self, other: &BitGrid) -> BitGrid {
+        let mut g = self.clone();
+        g.intersect_with(other);
+        g
+    }
+
+    pub fn difference(&self, other: &BitGrid) -> BitGrid {
+        let mut g = self.clone();
+        g.difference_with(other);
+        g
+    }
+
+    /// Set cells in row-major order.
+    pub fn iter(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.bits
+            .ones()
+            .map(|i| Coord::new(i / self.width, i % self.width))
+    }
+
+    /// Draws `grid` with every set cell replaced by `mark`.
+    pub fn overlay<'a, T>(&'a self, grid: &'a BasicGrid<T>, mark: AsciiByte) -> Overlay<'a, T> {
+        assert!(grid.width == self.width && grid.height == self.height);
+        Overlay {
+            grid,
+            mark: Box::new(move |c| self.test(c).then_some(mark)),
+        }
+    }
+}
+
+/// A packed set of `(Coord, Dir)` states, four bits per cell.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DirBitGrid {
+    bits: Bits,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirBitGrid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            bits: Bits::new(width * height * 4),
+            width,
+            height,
+        }
+    }
+
+    pub fn for_grid<T>(grid: &BasicGrid<T>) -> Self {
+        Self::new(grid.width, grid.height)
+    }
+
+    fn idx(&self, pos: Coord, dir: Dir) -> usize {
+        assert!(pos.col < self.width, "{:?} out of range", pos);
+        (pos.row * self.width + pos.col) * 4 + dir.index()
+    }
+
+    /// Marks `(pos, dir)`, returning `true` if it was not already set.
+    pub fn set(&mut self, pos: Coord, dir: Dir) -> bool {
+        let i = self.idx(pos, dir);
+        self.bits.set(i)
+    }
+
+    /// Unmarks `(pos, dir)`, returning `true` if it had been set.
+    pub fn clear(&mut self, pos: Coord, dir: Dir) -> bool {
+        let i = self.idx(pos, dir);
+        self.bits.clear(i)
+    }
+
+    pub fn test(&self, pos: Coord, dir: Dir) -> bool {
+        self.bits.test(self.idx(pos, dir))
+    }
+
+    /// True if `pos` is set for any direction.
+    pub fn test_any(&self, pos: Coord) -> bool {
+        Dir::ALL.iter().any(|d| self.test(pos, *d))
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.words.iter().all(|w| *w == 0)
+    }
+
+    /// Clears every state without reallocating.
+    pub fn reset(&mut self) {
+        self.bits.reset();
+    }
+
+    pub fn union_with(&mut self, other: &DirBitGrid) {
+        self.bits.combine(&other.bits, |a, b| a | b);
+    }
+
+    pub fn intersect_with(&mut self, other: &DirBitGrid) {
+        self.bits.combine(&other.bits, |a, b| a & b);
+    }
+
+    pub fn difference_with(&mut self, other: &DirBitGrid) {
+        self.bits.combine(&other.bits, |a, b| a & !b);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Coord, Dir)> + '_ {
+        self.bits.ones().map(|i| {
+            let cell = i / 4;
+            (
+                Coord::new(cell / self.width, cell % self.width),
+                Dir::ALL[i % 4],
+            )
+        })
+    }
+
+    /// The cells set for at least one direction.
+    pub fn cells(&self) -> BitGrid {
+        let mut g = BitGrid::new(self.width, self.height);
+        for (c, _) in self.iter() {
+            g.set(c);
+        }
+        g
+    }
+
+    /// Draws `grid` with each visited cell shown as its direction arrow, or
+    /// `+` where more than one direction was recorded.
+    pub fn overlay<'a, T>(&'a self, grid: &'a BasicGrid<T>) -> Overlay<'a, T> {
+        assert!(grid.width == self.width && grid.height == self.height);
+        Overlay {
+            grid,
+            mark: Box::new(move |c| {
+                let mut dirs = Dir::ALL.into_iter().filter(|d| self.test(c, *d));
+                match (dirs.next(), dirs.next()) {
+                    (None, _) => None,
+                    (Some(d), None) => Some(d.as_ascii_byte()),
+                    (Some(_), Some(_)) => Some(AsciiByte(b'+')),
+                }
+            }),
+        }
+    }
+}
+
+pub struct Overlay<'a, T> {
+    grid: &'a BasicGrid<T>,
+    mark: Box<dyn Fn(Coord) -> Option<AsciiByte> + 'a>,
+}
+
+impl<T> Display for Overlay<'_, T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in 0..self.grid.height {
+            for col in 0..self.grid.width {
+                let c = Coord::new(row, col);
+                match (self.mark)(c) {
+                    Some(m) => write!(f, "{}", m)?,
+                    None => write!(f, "{}", self.grid[c])?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bitgrid() {
+        let mut a = BitGrid::new(10, 10);
+        assert!(a.set(Coord::new(0, 0)));
+        assert!(!a.set(Coord::new(0, 0)));
+        a.set(Coord::new(6, 4));
+        a.set(Coord::new(9, 9));
+        assert_eq!(a.count(), 3);
+        assert_eq!(
+            a.iter().collect::<Vec<_>>(),
+            vec![Coord::new(0, 0), Coord::new(6, 4), Coord::new(9, 9)]
+        );
+
+        let mut b = BitGrid::new(10, 10);
+        b.set(Coord::new(6, 4));
+        b.set(Coord::new(1, 1));
+        assert_eq!(a.intersection(&b).count(), 1);
+        assert_eq!(a.union(&b).count(), 4);
+        assert_eq!(a.difference(&b).count(), 2);
+
+        assert!(a.clear(Coord::new(9, 9)));
+        assert!(!a.test(Coord::new(9, 9)));
+        a.reset();
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn test_dir_bitgrid() {
+        let grid: BasicGrid<AsciiByte> = BasicGrid::new(&["...", "..."]);
+        let mut seen = DirBitGrid::for_grid(&grid);
+        assert!(seen.set(Coord::new(0, 1), Dir::Up));
+        assert!(seen.set(Coord::new(0, 1), Dir::Left));
+        assert!(seen.set(Coord::new(1, 2), Dir::Right));
+        assert!(!seen.test(Coord::new(0, 1), Dir::Down));
+        assert_eq!(seen.count(), 3);
+        assert_eq!(seen.cells().count(), 2);
+        assert_eq!(seen.overlay(&grid).to_string(), ".+.\n..>\n");
+    }
+}
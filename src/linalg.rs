@@ -0,0 +1,526 @@
+use std::{
+    fmt::Debug,
+    ops::{Index, IndexMut},
+};
+
+use num_integer::Integer;
+use num_rational::Ratio;
+use num_traits::{One, Signed, Zero};
+
+/// Integer types the rationals are built from: `i64` gives `Rational64`,
+/// `BigInt` gives `BigRational`.
+pub trait Scalar: Clone + Debug + Integer + Signed {}
+
+impl<T> Scalar for T where T: Clone + Debug + Integer + Signed {}
+
+/// A dense matrix of exact rationals, stored row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Matrix<T: Scalar> {
+    pub rows: usize,
+    pub cols: usize,
+    data: Vec<Ratio<T>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution<T: Scalar> {
+    Inconsistent,
+    Unique(Vec<Ratio<T>>),
+    /// `particular` plus any combination of the `nullspace` vectors
+    Infinite {
+        particular: Vec<Ratio<T>>,
+        nullspace: Vec<Vec<Ratio<T>>>,
+    },
+}
+
+impl<T: Scalar> Matrix<T> {
+    pub fn from_fn<F>(rows: usize, cols: usize, mut f: F) -> Self
+    where
+        F: FnMut(usize, usize) -> Ratio<T>,
+    {
+        let data = (0..rows * cols).map(|i| f(i / cols, i % cols)).collect();
+        Self { rows, cols, data }
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self::from_fn(rows, cols, |_, _| Ratio::zero())
+    }
+
+    /// A matrix of whole numbers, one `Vec` per row.
+    pub fn from_rows(rows: &[Vec<T>]) -> Self {
+        let cols = rows.first().map_or(0, Vec::len);
+        assert!(rows.iter().all(|r| r.len() == cols), "ragged rows");
+        Self::from_fn(rows.len(), cols, |r, c| {
+            Ratio::from_integer(rows[r][c].clone())
+        })
+    }
+
+    pub fn row(&self, r: usize) -> &[Ratio<T>] {
+        &self.data[r * self.cols..(r + 1) * self.cols]
+    }
+
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        for c in 0..self.cols {
+            self.data.swap(a * self.cols + c, b * self.cols + c);
+        }
+    }
+
+    pub fn mul_vec(&self, x: &[Ratio<T>]) -> Vec<Ratio<T>> {
+        assert_eq!(x.len(), self.cols);
+        (0..self.rows)
+            .map(|r| {
+                self.row(r)
+                    .iter()
+                    .zip(x)
+                    .fold(Ratio::zero(), |acc, (a, b)| acc + a.clone() * b.clone())
+            })
+            .collect()
+    }
+
+    /// This matrix with `b` appended as an extra column.
+    pub fn with_column(&self, b: &[Ratio<T>]) -> Self {
+        assert_eq!(b.len(), self.rows);
+        Self::from_fn(self.rows, self.cols + 1, |r, c| {
+            if c == self.cols {
+                b[r].clone()
+            } else {
+                self[(r, c)].clone()
+            }
+        })
+    }
+
+    /// Reduced row echelon form by Gauss-Jordan elimination, with the
+    /// column of each row's leading one.
+    pub fn rref(&self) -> (Self, Vec<usize>) {
+        let mut m = self.clone();
+        let mut pivots: Vec<usize> = vec![];
+        for c in 0..m.cols {
+            let r = pivots.len();
+            if r == m.rows {
+                break;
+            }
+            let Some(p) = (r..m.rows).find(|i| !m[(*i, c)].is_zero()) else {
+                continue;
+            };
+            m.swap_rows(r, p);
+            let inv = m[(r, c)].recip();
+            for j in c..m.cols {
+                m[(r, j)] = m[(r, j)].clone() * inv.clone();
+            }
+            for i in (0..m.rows).filter(|i| *i != r) {
+                let f = m[(i, c)].clone();
+                if f.is_zero() {
+                    continue;
+                }
+                for j in c..m.cols {
+                    let v = m[(r, j)].clone() * f.clone();
+                    m[(i, j)] = m[(i, j)].clone() - v;
+                }
+            }
+            pivots.push(c);
+        }
+        (m, pivots)
+    }
+
+    pub fn rank(&self) -> usize {
+        self.rref().1.len()
+    }
+
+    /// A basis of the vectors `x` with `self * x == 0`, one per free column.
+    pub fn nullspace(&self) -> Vec<Vec<Ratio<T>>> {
+        let (r, pivots) = self.rref();
+        nullspace_of(&r, &pivots, self.cols)
+    }
+
+    pub fn solve(&self, b: &[Ratio<T>]) -> Solution<T> {
+        let (r, pivots) = self.with_column(b).rref();
+        if pivots.last() == Some(&self.cols) {
+            return Solution::Inconsistent;
+        }
+        let mut particular = vec![Ratio::zero(); self.cols];
+        for (row, p) in pivots.iter().enumerate() {
+            particular[*p] = r[(row, self.cols)].clone();
+        }
+        let nullspace = nullspace_of(&r, &pivots, self.cols);
+        if nullspace.is_empty() {
+            Solution::Unique(particular)
+        } else {
+            Solution::Infinite {
+                particular,
+                nullspace,
+            }
+        }
+    }
+
+    /// Some all-integer `x` with `self * x == b`, if there is one.
+    pub fn integer_solution(&self, b: &[T]) -> Option<Vec<T>> {
+        let b: Vec<Ratio<T>> = b.iter().cloned().map(Ratio::from_integer).collect();
+        match self.solve(&b) {
+            Solution::Inconsistent => None,
+            Solution::Unique(x) => integral(&x),
+            Solution::Infinite {
+                particular,
+                nullspace,
+            } => {
+                // each free variable is a coordinate of x, so it must be a
+                // whole number too, and whether the rest are depends only
+                // on it modulo the denominators of the basis
+                let period = nullspace
+                    .iter()
+                    .flatten()
+                    .fold(T::one(), |l, v| l.lcm(v.denom()));
+                let bounds = vec![period - T::one(); nullspace.len()];
+                let mut found = None;
+                search_box(&bounds, &mut vec![], &mut |t| {
+                    let x: Vec<Ratio<T>> = (0..self.cols)
+                        .map(|i| {
+                            nullspace
+                                .iter()
+                                .zip(t)
+                                .fold(particular[i].clone(), |acc, (n, t)| {
+                                    acc + n[i].clone() * Ratio::from_integer(t.clone())
+                                })
+                        })
+                        .collect();
+                    found = integral(&x);
+                    found.is_some()
+                });
+                found
+            }
+        }
+    }
+}
+
+impl<T: Scalar> Index<(usize, usize)> for Matrix<T> {
+    type Output = Ratio<T>;
+
+    fn index(&self, (r, c): (usize, usize)) -> &Self::Output {
+        &self.data[r * self.cols + c]
+    }
+}
+
+impl<T: Scalar> IndexMut<(usize, usize)> for Matrix<T> {
+    fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut Self::Output {
+        &mut self.data[r * self.cols + c]
+    }
+}
+
+fn nullspace_of<T: Scalar>(r: &Matrix<T>, pivots: &[usize], cols: usize) -> Vec<Vec<Ratio<T>>> {
+    (0..cols)
+        .filter(|c| !pivots.contains(c))
+        .map(|f| {
+            let mut v = vec![Ratio::zero(); cols];
+            v[f] = Ratio::one();
+            for (row, p) in pivots.iter().enumerate() {
+                v[*p] = -r[(row, f)].clone();
+            }
+            v
+        })
+        .collect()
+}
+
+fn integral<T: Scalar>(x: &[Ratio<T>]) -> Option<Vec<T>> {
+    x.iter()
+        .all(Ratio::is_integer)
+        .then(|| x.iter().map(Ratio::to_integer).collect())
+}
+
+/// Calls `f` on every point with `0 <= point[i] <= bounds[i]` until it
+/// returns `true`, which is passed back.
+fn search_box<T: Scalar>(
+    bounds: &[T],
+    point: &mut Vec<T>,
+    f: &mut dyn FnMut(&[T]) -> bool,
+) -> bool {
+    let Some(bound) = bounds.get(point.len()) else {
+        return f(point);
+    };
+    let mut t = T::zero();
+    while t <= *bound {
+        point.push(t.clone());
+        let done = search_box(bounds, point, f);
+        point.pop();
+        if done {
+            return true;
+        }
+        t = t + T::one();
+    }
+    false
+}
+
+/// Buttons that each move a fixed, non-negative amount along every axis,
+/// to be pressed a whole number of times to land exactly on `target`.
+#[derive(Clone, Debug)]
+pub struct Machine<T: Scalar> {
+    /// per button, how far one press moves along each axis
+    pub buttons: Vec<Vec<T>>,
+    /// per button, the cost of one press
+    pub costs: Vec<T>,
+    pub target: Vec<T>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Plan<T> {
+    pub presses: Vec<T>,
+    pub cost: T,
+    /// dimensions the press counts could vary in; 0 when they're forced
+    pub freedom: usize,
+}
+
+impl<T: Scalar> Machine<T> {
+    pub fn new(buttons: Vec<Vec<T>>, costs: Vec<T>, target: Vec<T>) -> Self {
+        assert_eq!(buttons.len(), costs.len());
+        assert!(buttons.iter().all(|b| b.len() == target.len()));
+        assert!(
+            buttons
+                .iter()
+                .flatten()
+                .chain(costs.iter())
+                .all(|v| !v.is_negative()),
+            "button moves and costs must be non-negative"
+        );
+        Self {
+            buttons,
+            costs,
+            target,
+        }
+    }
+
+    /// One row per axis, one column per button.
+    pub fn matrix(&self) -> Matrix<T> {
+        Matrix::from_fn(self.target.len(), self.buttons.len(), |r, c| {
+            Ratio::from_integer(self.buttons[c][r].clone())
+        })
+    }
+
+    /// The cheapest whole, non-negative presses reaching the target.
+    ///
+    /// Press counts for the buttons left free by elimination are searched
+    /// up to where they'd overshoot, except for the last one, which is
+    /// solved directly. That makes one free button (parallel buttons, or
+    /// one more button than axes) cheap however large the target is.
+    pub fn cheapest(&self) -> Option<Plan<T>> {
+        let n = self.buttons.len();
+        let b: Vec<Ratio<T>> = self
+            .target
+            .iter()
+            .cloned()
+            .map(Ratio::from_integer)
+            .collect();
+        let (r, pivots) = self.matrix().with_column(&b).rref();
+        if pivots.last() == Some(&n) {
+            return None;
+        }
+        let free: Vec<usize> = (0..n).filter(|c| !pivots.contains(c)).collect();
+        let bounds: Vec<T> = free.iter().map(|f| self.max_presses(*f)).collect();
+
+        let mut best: Option<Plan<T>> = None;
+        let mut consider = |presses: Vec<T>| {
+            let cost = presses
+                .iter()
+                .zip(self.costs.iter())
+                .fold(T::zero(), |acc, (p, c)| acc + p.clone() * c.clone());
+            if best.as_ref().is_none_or(|b| cost < b.cost) {
+                best = Some(Plan {
+                    presses,
+                    cost,
+                    freedom: free.len(),
+                });
+            }
+        };
+
+        // each pivot press count is rhs - sum(coefficient * free presses)
+        let pivot_values = |fixed: &[T]| -> Vec<Ratio<T>> {
+            (0..pivots.len())
+                .map(|row| {
+                    free.iter()
+                        .zip(fixed)
+                        .fold(r[(row, n)].clone(), |acc, (f, t)| {
+                            acc - r[(row, *f)].clone() * Ratio::from_integer(t.clone())
+                        })
+                })
+                .collect()
+        };
+
+        let Some((last, others)) = free.split_last() else {
+            let values = pivot_values(&[]);
+            if let Some(values) = integral(&values).filter(|v| v.iter().all(|x| !x.is_negative())) {
+                let mut presses = vec![T::zero(); n];
+                for (p, v) in pivots.iter().zip(values) {
+                    presses[*p] = v;
+                }
+                consider(presses);
+            }
+            return best;
+        };
+
+        search_box(&bounds[..others.len()], &mut vec![], &mut |fixed| {
+            // with the other free presses fixed, each pivot is c - k * t in
+            // the last free button's presses t
+            let c = pivot_values(fixed);
+            let k: Vec<Ratio<T>> = (0..pivots.len())
+                .map(|row| r[(row, *last)].clone())
+                .collect();
+            let slope = pivots.iter().zip(k.iter()).fold(
+                Ratio::from_integer(self.costs[*last].clone()),
+                |acc, (p, k)| acc - Ratio::from_integer(self.costs[*p].clone()) * k.clone(),
+            );
+            if let Some(t) = cheapest_t(&c, &k, bounds[others.len()].clone(), slope) {
+                let mut presses = vec![T::zero(); n];
+                for (f, v) in others.iter().zip(fixed) {
+                    presses[*f] = v.clone();
+                }
+                let t_ratio = Ratio::from_integer(t.clone());
+                for ((p, c), k) in pivots.iter().zip(c).zip(k) {
+                    presses[*p] = (c - k * t_ratio.clone()).to_integer();
+                }
+                presses[*last] = t;
+                consider(presses);
+            }
+            false
+        });
+        best
+    }
+
+    /// Presses of button `b` after which some axis would be overshot.
+    fn max_presses(&self, b: usize) -> T {
+        self.buttons[b]
+            .iter()
+            .zip(self.target.iter())
+            .filter(|(m, _)| m.is_positive())
+            .map(|(m, t)| t.div_floor(m))
+            .min()
+            .unwrap_or_else(T::zero)
+    }
+}
+
+/// The `t` in `0..=hi` keeping every `c - k * t` a whole, non-negative
+/// number, at the cheap end of the range given the cost `slope`.
+fn cheapest_t<T: Scalar>(c: &[Ratio<T>], k: &[Ratio<T>], hi: T, slope: Ratio<T>) -> Option<T> {
+    let (mut lo, mut hi) = (T::zero(), hi);
+    for (c, k) in c.iter().zip(k) {
+        if k.is_positive() {
+            hi = hi.min((c / k).floor().to_integer());
+        } else if k.is_negative() {
+            lo = lo.max((c / k).ceil().to_integer());
+        } else if c.is_negative() {
+            return None;
+        }
+    }
+    if lo > hi {
+        return None;
+    }
+    // whether c - k * t is whole only depends on t modulo the denominators
+    let period = k.iter().fold(T::one(), |l, k| l.lcm(k.denom()));
+    let mut best: Option<(Ratio<T>, T)> = None;
+    let mut rho = T::zero();
+    while rho < period {
+        let whole = c
+            .iter()
+            .zip(k)
+            .all(|(c, k)| (c - k * Ratio::from_integer(rho.clone())).is_integer());
+        if whole {
+            let t = if slope.is_negative() {
+                hi.clone() - (hi.clone() - rho.clone()).mod_floor(&period)
+            } else {
+                lo.clone() + (rho.clone() - lo.clone()).mod_floor(&period)
+            };
+            if lo <= t && t <= hi {
+                let cost = slope.clone() * Ratio::from_integer(t.clone());
+                if best.as_ref().is_none_or(|(b, _)| cost < *b) {
+                    best = Some((cost, t));
+                }
+            }
+        }
+        rho = rho + T::one();
+    }
+    best.map(|(_, t)| t)
+}
+
+#[cfg(test)]
+mod test {
+    use num_bigint::BigInt;
+    use num_rational::Rational64;
+
+    use super::*;
+
+    fn r(n: i64, d: i64) -> Rational64 {
+        Rational64::new(n, d)
+    }
+
+    #[test]
+    fn test_elimination() {
+        let m: Matrix<i64> =
+            Matrix::from_rows(&[vec![1, 2, 1, 4], vec![2, 4, 0, 6], vec![1, 2, 2, 5]]);
+        assert_eq!(m.rank(), 2);
+        let null = m.nullspace();
+        assert_eq!(null.len(), 2);
+        for v in null.iter() {
+            assert!(m.mul_vec(v).iter().all(Zero::is_zero));
+        }
+
+        let b = [r(6, 1), r(10, 1), r(7, 1)];
+        match m.solve(&b) {
+            Solution::Infinite { particular, .. } => assert_eq!(m.mul_vec(&particular), b),
+            s => panic!("{s:?}"),
+        }
+        assert_eq!(
+            m.solve(&[r(6, 1), r(10, 1), r(8, 1)]),
+            Solution::Inconsistent
+        );
+
+        let m: Matrix<i64> = Matrix::from_rows(&[vec![94, 22], vec![34, 67]]);
+        assert_eq!(
+            m.solve(&[r(8400, 1), r(5400, 1)]),
+            Solution::Unique(vec![r(80, 1), r(40, 1)])
+        );
+
+        let big: Matrix<BigInt> = Matrix::from_rows(&[vec![BigInt::from(3), BigInt::from(1)]]);
+        let x = big.integer_solution(&[BigInt::from(10).pow(40)]).unwrap();
+        assert_eq!(BigInt::from(3) * &x[0] + &x[1], BigInt::from(10).pow(40));
+    }
+
+    #[test]
+    fn test_integer_solution() {
+        let m: Matrix<i64> = Matrix::from_rows(&[vec![2, 4]]);
+        let x = m.integer_solution(&[6]).unwrap();
+        assert_eq!(2 * x[0] + 4 * x[1], 6);
+        assert_eq!(m.integer_solution(&[5]), None);
+
+        let m: Matrix<i64> = Matrix::from_rows(&[vec![2, 0], vec![0, 3]]);
+        assert_eq!(m.integer_solution(&[4, 9]), Some(vec![2, 3]));
+        assert_eq!(m.integer_solution(&[4, 8]), None);
+    }
+
+    #[test]
+    fn test_machine() {
+        // three axes and a fourth button that moves along all of them
+        let m = Machine::new(
+            vec![vec![1, 0, 0], vec![0, 1, 0], vec![0, 0, 1], vec![1, 1, 1]],
+            vec![1, 1, 1, 2],
+            vec![3, 4, 5],
+        );
+        assert_eq!(
+            m.cheapest(),
+            Some(Plan {
+                presses: vec![0, 1, 2, 3],
+                cost: 9,
+                freedom: 1,
+            })
+        );
+
+        // three buttons on one axis: two free
+        let m = Machine::new(vec![vec![5], vec![3], vec![1]], vec![6, 4, 1], vec![11]);
+        assert_eq!(
+            m.cheapest().map(|p| (p.presses, p.cost)),
+            Some((vec![0, 0, 11], 11))
+        );
+        let m = Machine::new(vec![vec![5], vec![3], vec![1]], vec![1, 3, 4], vec![11]);
+        assert_eq!(
+            m.cheapest().map(|p| (p.presses, p.cost)),
+            Some((vec![2, 0, 1], 6))
+        );
+
+        let m = Machine::new(vec![vec![2, 4], vec![4, 8]], vec![1, 1], vec![3, 6]);
+        assert_eq!(m.cheapest(), None);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::{BasicGrid, Coord};
+
+/// One of the eight rotations/reflections of a template. The template is
+/// flipped left-to-right first (if `flipped`), then turned clockwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub struct Symmetry {
+    pub quarter_turns: u8,
+    pub flipped: bool,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::new(0, false),
+        Symmetry::new(1, false),
+        Symmetry::new(2, false),
+        Symmetry::new(3, false),
+        Symmetry::new(0, true),
+        Symmetry::new(1, true),
+        Symmetry::new(2, true),
+        Symmetry::new(3, true),
+    ];
+
+    pub const fn new(quarter_turns: u8, flipped: bool) -> Self {
+        Self {
+            quarter_turns: quarter_turns % 4,
+            flipped,
+        }
+    }
+}
+
+/// A small rectangular pattern of cells, where `None` matches anything.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template<T> {
+    cells: Box<[Option<T>]>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl<T> Template<T>
+where
+    T: TryFrom<u8>,
+{
+    /// Parses a template from text, one row per line. Leading and trailing
+    /// blank lines are ignored; `wildcard` marks cells that match anything.
+    pub fn parse(text: &str, wildcard: u8) -> Result<Self, T::Error> {
+        let lines: Vec<&str> = text.trim_matches('\n').lines().collect();
+        let height = lines.len();
+        let width = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+        let mut cells = Vec::with_capacity(width * height);
+        for line in lines {
+            for b in line.bytes() {
+                cells.push(if b == wildcard {
+                    None
+                } else {
+                    Some(b.try_into()?)
+                });
+            }
+            // ragged rows are padded with wildcards
+            cells.extend((line.len()..width).map(|_| None));
+        }
+        Ok(Self {
+            cells: cells.into_boxed_slice(),
+            width,
+            height,
+        })
+    }
+}
+
+impl<T> Template<T> {
+    pub fn at(&self, row: usize, col: usize) -> Option<&T> {
+        self.cells[row * self.width + col].as_ref()
+    }
+}
+
+impl<T> Template<T>
+where
+    T: Clone,
+{
+    pub fn transformed(&self, sym: Symmetry) -> Self {
+        let (w, h) = (self.width, self.height);
+        let mut t = self.clone();
+        if sym.flipped {
+            t.cells = (0..w * h)
+                .map(|i| self.cells[(i / w) * w + (w - 1 - i % w)].clone())
+                .collect();
+        }
+        for _ in 0..sym.quarter_turns {
+            // clockwise: new (r, c) comes from old (h - 1 - c, r)
+            let (ow, oh) = (t.width, t.height);
+            let cells = (0..ow * oh)
+                .map(|i| {
+                    let (r, c) = (i / oh, i % oh);
+                    t.cells[(oh - 1 - c) * ow + r].clone()
+                })
+                .collect();
+            t = Template {
+                cells,
+                width: oh,
+                height: ow,
+            };
+        }
+        t
+    }
+}
+
+impl<T> Template<T>
+where
+    T: Clone + PartialEq,
+{
+    /// The distinct rotations/reflections of this template, each paired
+    /// with the first symmetry that produces it.
+    pub fn variants(&self) -> Vec<(Symmetry, Template<T>)> {
+        let mut out: Vec<(Symmetry, Template<T>)> = vec![];
+        for sym in Symmetry::ALL {
+            let t = self.transformed(sym);
+            if !out.iter().any(|(_, seen)| *seen == t) {
+                out.push((sym, t));
+            }
+        }
+        out
+    }
+
+    pub fn matches_at(&self, grid: &BasicGrid<T>, anchor: Coord) -> bool {
+        if anchor.row + self.height > grid.height || anchor.col + self.width > grid.width {
+            return false;
+        }
+        (0..self.height).all(|r| {
+            (0..self.width).all(|c| match self.at(r, c) {
+                None => true,
+                Some(v) => grid[Coord::new(anchor.row + r, anchor.col + c)] == *v,
+            })
+        })
+    }
+
+    /// Top-left corners of every placement where the template fits.
+    pub fn find_in(&self, grid: &BasicGrid<T>) -> Vec<Coord> {
+        if self.height > grid.height || self.width > grid.width {
+            return vec![];
+        }
+        (0..=grid.height - self.height)
+            .flat_map(|row| (0..=grid.width - self.width).map(move |col| Coord::new(row, col)))
+            .filter(|c| self.matches_at(grid, *c))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Match {
+    /// Index of the template, in the order it was added.
+    pub template: usize,
+    pub symmetry: Symmetry,
+    pub anchor: Coord,
+}
+
+/// Matches several templates at once, optionally under all symmetries.
+pub struct Matcher<T> {
+    entries: Vec<(usize, Symmetry, Template<T>)>,
+    count: usize,
+}
+
+impl<T> Default for Matcher<T> {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            count: 0,
+        }
+    }
+}
+
+impl<T> Matcher<T>
+where
+    T: Clone + PartialEq,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a template as written, returning its index.
+    pub fn add(&mut self, template: Template<T>) -> usize {
+        let id = self.count;
+        self.count += 1;
+        self.entries.push((id, Symmetry::default(), template));
+        id
+    }
+
+    /// Adds every distinct rotation/reflection of a template under one index.
+    pub fn add_all_symmetries(&mut self, template: Template<T>) -> usize {
+        let id = self.count;
+        self.count += 1;
+        for (sym, t) in template.variants() {
+            self.entries.push((id, sym, t));
+        }
+        id
+    }
+
+    pub fn find(&self, grid: &BasicGrid<T>) -> Vec<Match> {
+        let mut matches: Vec<Match> = self
+            .entries
+            .iter()
+            .flat_map(|(id, sym, t)| {
+                t.find_in(grid).into_iter().map(|anchor| Match {
+                    template: *id,
+                    symmetry: *sym,
+                    anchor,
+                })
+            })
+            .collect();
+        matches.sort_by_key(|m| (m.anchor.row, m.anchor.col, m.template));
+        matches
+    }
+
+    pub fn count(&self, grid: &BasicGrid<T>) -> usize {
+        self.entries
+            .iter()
+            .map(|(_, _, t)| t.find_in(grid).len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AsciiByte;
+
+    static WORDS: &str = "MMMSXXMASM
+MSAMXMSMSA
+AMXSXMAAMM
+MSAMASMSMX
+XMASAMXAMM
+XXAMMXXAMA
+SMSMSASXSS
+SAXAMASAAA
+MAMMMXMMMM
+MXMXAXMASX";
+
+    #[test]
+    fn test_transform() {
+        let t: Template<u8> = Template::parse("ab\ncd\nef", b'?').unwrap();
+        let r = t.transformed(Symmetry::new(1, false));
+        assert_eq!((r.width, r.height), (3, 2));
+        assert_eq!(r.at(0, 0), Some(&b'e'));
+        assert_eq!(r.at(1, 2), Some(&b'b'));
+        let f = t.transformed(Symmetry::new(0, true));
+        assert_eq!(f.at(0, 0), Some(&b'b'));
+        assert_eq!(t.variants().len(), 8);
+    }
+
+    #[test]
+    fn test_xmas() {
+        let lines: Vec<&str> = WORDS.lines().collect();
+        let grid: BasicGrid<AsciiByte> = BasicGrid::new(&lines);
+
+        let x_mas: Template<AsciiByte> = Template::parse("M.S\n.A.\nM.S", b'.').unwrap();
+        assert_eq!(x_mas.variants().len(), 4);
+
+        let mut matcher = Matcher::new();
+        let cross = matcher.add_all_symmetries(x_mas);
+        let word = matcher.add_all_symmetries(Template::parse("XMAS", b'.').unwrap());
+        let found = matcher.find(&grid);
+        assert_eq!(found.iter().filter(|m| m.template == cross).count(), 9);
+        // horizontal and vertical XMAS only; diagonals aren't symmetries
+        assert_eq!(found.iter().filter(|m| m.template == word).count(), 8);
+        assert_eq!(matcher.count(&grid), found.len());
+    }
+}
@@ -0,0 +1,310 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// A deterministic, step-by-step simulation. Two states with the same `key`
+/// must evolve identically, which is what makes cycle detection valid.
+pub trait Simulation: Clone {
+    type Key: Hash + Eq;
+
+    /// Advances one step, returning `false` if the simulation has ended and
+    /// the state was left unchanged.
+    fn step(&mut self) -> bool;
+
+    fn key(&self) -> Self::Key;
+}
+
+/// Steps `prefix` states lead into a cycle that repeats every `period` steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    pub prefix: usize,
+    pub period: usize,
+}
+
+impl Cycle {
+    /// The earliest step equivalent to step `n`.
+    pub fn reduce(&self, n: usize) -> usize {
+        if n < self.prefix {
+            n
+        } else {
+            self.prefix + (n - self.prefix) % self.period
+        }
+    }
+}
+
+pub struct Runner<S> {
+    state: S,
+    steps: usize,
+    halted: bool,
+}
+
+impl<S> Runner<S>
+where
+    S: Simulation,
+{
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn into_state(self) -> S {
+        self.state
+    }
+
+    /// Steps taken since the runner was created.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    fn step(&mut self) -> bool {
+        if !self.halted && self.state.step() {
+            self.steps += 1;
+            true
+        } else {
+            self.halted = true;
+            false
+        }
+    }
+
+    /// Runs up to `n` steps, returning how many were actually taken.
+    pub fn run(&mut self, n: usize) -> usize {
+        (0..n).take_while(|_| self.step()).count()
+    }
+
+    /// Steps until `pred` holds for the current state (which may already be
+    /// the case), returning the step count then. `None` if the simulation
+    /// ends first.
+    pub fn run_until<F>(&mut self, mut pred: F) -> Option<usize>
+    where
+        F: FnMut(&S) -> bool,
+    {
+        loop {
+            if pred(&self.state) {
+                return Some(self.steps);
+            }
+            if !self.step() {
+                return None;
+            }
+        }
+    }
+
+    /// Finds the cycle reached from the current state by remembering every
+    /// key seen. Fast, but holds one key per step, and never returns for a
+    /// simulation that neither ends nor repeats.
+    pub fn find_cycle(&self) -> Option<Cycle> {
+        let mut seen: HashMap<S::Key, usize> = Default::default();
+        let mut s = self.state.clone();
+        let mut i = 0;
+        loop {
+            if let Some(first) = seen.insert(s.key(), i) {
+                return Some(Cycle {
+                    prefix: first,
+                    period: i - first,
+                });
+            }
+            if !s.step() {
+                return None;
+            }
+            i += 1;
+        }
+    }
+
+    /// Brent's algorithm: constant memory, roughly one step per state.
+    /// Gives up after `limit` steps ahead of the current state.
+    pub fn find_cycle_brent(&self, limit: usize) -> Option<Cycle> {
+        let mut power = 1;
+        let mut period = 1;
+        let mut tortoise = self.state.clone();
+        let mut hare = self.state.clone();
+        if limit == 0 || !hare.step() {
+            return None;
+        }
+        let mut ahead = 1;
+        while tortoise.key() != hare.key() {
+            if power == period {
+                tortoise = hare.clone();
+                power *= 2;
+                period = 0;
+            }
+            if ahead == limit || !hare.step() {
+                return None;
+            }
+            ahead += 1;
+            period += 1;
+        }
+        self.cycle_start(period)
+    }
+
+    /// Floyd's tortoise and hare: constant memory, three steps per state.
+    /// Gives up after `limit` steps ahead of the current state.
+    pub fn find_cycle_floyd(&self, limit: usize) -> Option<Cycle> {
+        let mut tortoise = self.state.clone();
+        let mut hare = self.state.clone();
+        let mut ahead = 0;
+        loop {
+            if ahead + 2 > limit || !(hare.step() && hare.step()) {
+                return None;
+            }
+            ahead += 2;
+            tortoise.step();
+            if tortoise.key() == hare.key() {
+                break;
+            }
+        }
+        let mut period = 1;
+        hare = tortoise.clone();
+        hare.step();
+        while tortoise.key() != hare.key() {
+            hare.step();
+            period += 1;
+        }
+        self.cycle_start(period)
+    }
+
+    fn cycle_start(&self, period: usize) -> Option<Cycle> {
+        let mut tortoise = self.state.clone();
+        let mut hare = self.state.clone();
+        for _ in 0..period {
+            hare.step();
+        }
+        let mut prefix = 0;
+        while tortoise.key() != hare.key() {
+            tortoise.step();
+            hare.step();
+            prefix += 1;
+        }
+        Some(Cycle { prefix, period })
+    }
+
+    /// Moves to absolute step `n`, skipping whole cycles where possible.
+    /// Only looks for a cycle within the steps to go, so it costs at most a
+    /// few times plain stepping. Returns `false` if the simulation ends
+    /// before reaching it.
+    pub fn advance_to(&mut self, n: usize) -> bool {
+        assert!(
+            n >= self.steps,
+            "cannot rewind from {} to {}",
+            self.steps,
+            n
+        );
+        let remaining = n - self.steps;
+        let todo = match self.find_cycle_brent(remaining) {
+            Some(cycle) => cycle.reduce(remaining),
+            None => remaining,
+        };
+        let taken = self.run(todo);
+        if taken == todo {
+            self.steps = n;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// x -> x^2 + 1 mod m, a classic rho-shaped sequence
+    #[derive(Clone)]
+    struct Rho {
+        x: u64,
+        m: u64,
+    }
+
+    impl Simulation for Rho {
+        type Key = u64;
+
+        fn step(&mut self) -> bool {
+            self.x = (self.x * self.x + 1) % self.m;
+            true
+        }
+
+        fn key(&self) -> u64 {
+            self.x
+        }
+    }
+
+    #[derive(Clone)]
+    struct Countdown(u32);
+
+    impl Simulation for Countdown {
+        type Key = u32;
+
+        fn step(&mut self) -> bool {
+            self.0.checked_sub(1).map(|n| self.0 = n).is_some()
+        }
+
+        fn key(&self) -> u32 {
+            self.0
+        }
+    }
+
+    /// counts up forever, never repeating
+    #[derive(Clone)]
+    struct Counter(u64);
+
+    impl Simulation for Counter {
+        type Key = u64;
+
+        fn step(&mut self) -> bool {
+            self.0 += 1;
+            true
+        }
+
+        fn key(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        let r = Runner::new(Rho { x: 3, m: 255 });
+        let cycle = r.find_cycle().unwrap();
+        assert_eq!(r.find_cycle_brent(1000), Some(cycle));
+        assert_eq!(r.find_cycle_floyd(1000), Some(cycle));
+        assert_eq!(r.find_cycle_brent(1), None);
+        assert_eq!(r.find_cycle_floyd(1), None);
+
+        let mut slow = Runner::new(Rho { x: 3, m: 255 });
+        slow.run(1_000_003);
+        let mut fast = Runner::new(Rho { x: 3, m: 255 });
+        assert!(fast.advance_to(1_000_003));
+        assert_eq!(fast.steps(), slow.steps());
+        assert_eq!(fast.state().x, slow.state().x);
+    }
+
+    #[test]
+    fn test_halting() {
+        let mut r = Runner::new(Countdown(10));
+        assert_eq!(r.find_cycle(), None);
+        assert_eq!(r.find_cycle_brent(1000), None);
+        assert_eq!(r.find_cycle_floyd(1000), None);
+        assert_eq!(r.run_until(|c| c.0 == 4), Some(6));
+        assert_eq!(r.run(100), 4);
+        assert!(r.halted());
+        assert!(!r.advance_to(20));
+    }
+
+    #[test]
+    fn test_no_cycle() {
+        let mut r = Runner::new(Counter(0));
+        assert_eq!(r.find_cycle_brent(10_000), None);
+        assert_eq!(r.find_cycle_floyd(10_000), None);
+        assert!(r.advance_to(1));
+        assert!(r.advance_to(r.steps() + 1));
+        assert!(r.advance_to(100_000));
+        assert_eq!(r.steps(), 100_000);
+        assert_eq!(r.state().0, 100_000);
+    }
+}
@@ -5,6 +5,16 @@ use std::{
     io::{BufReader, Read},
 };
 
+// lets `#[derive(GridCell)]` refer to `::utils` from inside this crate
+extern crate self as utils;
+
+mod bitgrid;
+pub mod linalg;
+pub mod pattern;
+pub mod sim;
+pub use bitgrid::{BitGrid, DirBitGrid, Overlay};
+pub use grid_cell_derive::GridCell;
+
 pub fn input<T: Read>(r: T) -> std::io::BufReader<T> {
     BufReader::new(r)
 }
@@ -28,6 +38,46 @@ impl From<u8> for AsciiByte {
         Self(value)
     }
 }
+/// A cell type that is read from and drawn as a single ASCII character.
+/// Usually derived with `#[derive(GridCell)]`.
+pub trait GridCell: Sized {
+    /// Every character that parses to some cell.
+    const CHARS: &'static [u8];
+
+    fn to_byte(&self) -> u8;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellError {
+    pub byte: u8,
+    pub cell_type: &'static str,
+    pub expected: &'static [u8],
+}
+
+impl CellError {
+    pub fn new<T: GridCell>(byte: u8) -> Self {
+        Self {
+            byte,
+            cell_type: std::any::type_name::<T>(),
+            expected: T::CHARS,
+        }
+    }
+}
+
+impl Display for CellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid {} '{}', expected one of \"{}\"",
+            self.cell_type,
+            std::ascii::escape_default(self.byte),
+            self.expected.escape_ascii()
+        )
+    }
+}
+
+impl std::error::Error for CellError {}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Coord {
     pub row: usize,
@@ -72,6 +122,85 @@ impl Coord {
     }
 }
 
+impl std::ops::Sub for Coord {
+    type Output = Vec2;
+
+    fn sub(self, rhs: Self) -> Vec2 {
+        Vec2::new(
+            self.row as isize - rhs.row as isize,
+            self.col as isize - rhs.col as isize,
+        )
+    }
+}
+
+impl Coord {
+    /// `self` moved by `v`, if that doesn't go negative.
+    pub fn offset(&self, v: Vec2) -> Option<Self> {
+        Some(Self {
+            row: self.row.checked_add_signed(v.row)?,
+            col: self.col.checked_add_signed(v.col)?,
+        })
+    }
+}
+
+/// A signed offset between two `Coord`s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Vec2 {
+    pub row: isize,
+    pub col: isize,
+}
+
+impl Vec2 {
+    pub fn new(row: isize, col: isize) -> Self {
+        Self { row, col }
+    }
+
+    /// The shortest vector in the same direction with integer components.
+    pub fn primitive(&self) -> Self {
+        let (mut a, mut b) = (self.row.unsigned_abs(), self.col.unsigned_abs());
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        if a == 0 {
+            *self
+        } else {
+            Self::new(self.row / a as isize, self.col / a as isize)
+        }
+    }
+}
+
+impl std::ops::Add for Vec2 {
+    type Output = Vec2;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.row + rhs.row, self.col + rhs.col)
+    }
+}
+
+impl std::ops::Sub for Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.row - rhs.row, self.col - rhs.col)
+    }
+}
+
+impl std::ops::Neg for Vec2 {
+    type Output = Vec2;
+
+    fn neg(self) -> Self {
+        Self::new(-self.row, -self.col)
+    }
+}
+
+impl std::ops::Mul<isize> for Vec2 {
+    type Output = Vec2;
+
+    fn mul(self, k: isize) -> Self {
+        Self::new(self.row * k, self.col * k)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Dir {
     Up,
@@ -81,6 +210,17 @@ pub enum Dir {
 }
 
 impl Dir {
+    pub const ALL: [Dir; 4] = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+
+    pub fn index(self) -> usize {
+        match self {
+            Dir::Up => 0,
+            Dir::Down => 1,
+            Dir::Left => 2,
+            Dir::Right => 3,
+        }
+    }
+
     pub fn turn_right(self) -> Self {
         match self {
             Dir::Up => Dir::Right,
@@ -98,6 +238,16 @@ impl Dir {
             Dir::Right => Dir::Up,
         }
     }
+
+    pub fn reverse(self) -> Self {
+        match self {
+            Dir::Up => Dir::Down,
+            Dir::Down => Dir::Up,
+            Dir::Left => Dir::Right,
+            Dir::Right => Dir::Left,
+        }
+    }
+
     pub fn as_ascii_byte(&self) -> AsciiByte {
         match self {
             Dir::Up => AsciiByte(b'^'),
@@ -106,6 +256,17 @@ impl Dir {
             Dir::Right => AsciiByte(b'>'),
         }
     }
+
+    /// The direction an arrow glyph (`^`, `v`, `<`, `>`) points.
+    pub fn from_ascii_byte(b: AsciiByte) -> Option<Self> {
+        match b.0 {
+            b'^' => Some(Dir::Up),
+            b'v' => Some(Dir::Down),
+            b'<' => Some(Dir::Left),
+            b'>' => Some(Dir::Right),
+            _ => None,
+        }
+    }
 }
 
 pub struct BasicGrid<T> {
@@ -142,9 +303,9 @@ where
 
 impl<T> BasicGrid<T>
 where
-    T: From<u8>,
+    T: TryFrom<u8>,
 {
-    pub fn new(lines: &[&str]) -> Self {
+    pub fn try_new(lines: &[&str]) -> Result<Self, T::Error> {
         let width = lines[0].len();
         let height = lines.len();
         let mut data: Vec<T> = Vec::with_capacity(width * height);
@@ -152,19 +313,86 @@ where
         for line in lines {
             let bytes = line.as_bytes();
             for b in bytes {
-                data.push((*b).into());
+                data.push((*b).try_into()?);
             }
         }
 
-        BasicGrid {
+        Ok(BasicGrid {
             width,
             height,
             data: data.into_boxed_slice(),
-        }
+        })
+    }
+}
+
+impl<T> BasicGrid<T>
+where
+    T: TryFrom<u8>,
+    T::Error: Debug,
+{
+    pub fn new(lines: &[&str]) -> Self {
+        Self::try_new(lines).unwrap()
     }
 }
 
 impl<T> BasicGrid<T> {
+    pub fn from_fn<F>(width: usize, height: usize, mut f: F) -> Self
+    where
+        F: FnMut(Coord) -> T,
+    {
+        let data: Vec<T> = (0..width * height)
+            .map(|i| f(Coord::new(i / width, i % width)))
+            .collect();
+        BasicGrid {
+            data: data.into_boxed_slice(),
+            width,
+            height,
+        }
+    }
+
+    pub fn map<U, F>(&self, f: F) -> BasicGrid<U>
+    where
+        F: FnMut(&T) -> U,
+    {
+        BasicGrid {
+            data: self.data.iter().map(f).collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Combines two grids of the same size cell by cell.
+    pub fn zip_with<U, V, F>(&self, other: &BasicGrid<U>, mut f: F) -> BasicGrid<V>
+    where
+        F: FnMut(&T, &U) -> V,
+    {
+        assert!(
+            self.width == other.width && self.height == other.height,
+            "grid sizes differ: {}x{} vs {}x{}",
+            self.width,
+            self.height,
+            other.width,
+            other.height
+        );
+        BasicGrid {
+            data: self
+                .data
+                .iter()
+                .zip(other.data.iter())
+                .map(|(a, b)| f(a, b))
+                .collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    pub fn map_inplace<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut T),
+    {
+        self.data.iter_mut().for_each(f);
+    }
+
     pub fn find_with<F>(&self, pred: F) -> Vec<Coord>
     where
         F: Fn(&T) -> bool,
@@ -379,4 +607,60 @@ mod test {
         assert_eq!(grid.get(Coord::new(3, 0), Dir::Up, 1), Some(b'8'));
         assert_eq!(grid.get(Coord::new(0, 0), Dir::Right, 1), Some(b'9'));
     }
+
+    #[test]
+    fn test_vec2() {
+        let d = Coord::new(2, 8) - Coord::new(6, 2);
+        assert_eq!(d, Vec2::new(-4, 6));
+        assert_eq!(d.primitive(), Vec2::new(-2, 3));
+        assert_eq!(
+            Coord::new(6, 2).offset(d.primitive() * 2),
+            Some(Coord::new(2, 8))
+        );
+        assert_eq!(Coord::new(1, 1).offset(-d), None);
+    }
+
+    #[derive(Debug, PartialEq, GridCell)]
+    enum Cell {
+        #[cell('#')]
+        Wall,
+        #[cell('.', default)]
+        Open,
+        #[cell('S')]
+        Start(Vec<usize>),
+    }
+
+    #[derive(Debug, PartialEq, GridCell)]
+    #[cell('0'..='9')]
+    struct Digit(u8);
+
+    #[test]
+    fn test_grid_cell() {
+        assert_eq!(Cell::try_from(b'#'), Ok(Cell::Wall));
+        assert_eq!(Cell::try_from(b'S'), Ok(Cell::Start(vec![])));
+        assert_eq!(Cell::default(), Cell::Open);
+        assert_eq!(Cell::Start(vec![1]).to_string(), "S");
+        assert_eq!(Cell::CHARS, b"#.S");
+        let err = Cell::try_from(b'x').unwrap_err();
+        assert_eq!(err.byte, b'x');
+        assert!(err.to_string().ends_with("expected one of \"#.S\""));
+
+        let grid: BasicGrid<Digit> = BasicGrid::new(&["0129"]);
+        assert_eq!(grid[Coord::new(0, 3)], Digit(9));
+        assert_eq!(grid[Coord::new(0, 2)].to_string(), "2");
+        assert!(BasicGrid::<Digit>::try_new(&["01a"]).is_err());
+    }
+
+    #[test]
+    fn test_map() {
+        let grid = BasicGrid::from_fn(3, 2, |c| c.row * 10 + c.col);
+        assert_eq!(grid[Coord::new(1, 2)], 12);
+        let odd = grid.map(|v| v % 2 == 1);
+        assert!(odd[Coord::new(0, 1)] && !odd[Coord::new(1, 0)]);
+        let mut sum = grid.zip_with(&odd, |v, o| if *o { *v } else { 0 });
+        assert_eq!(sum[Coord::new(1, 1)], 11);
+        assert_eq!(sum[Coord::new(1, 2)], 0);
+        sum.map_inplace(|v| *v += 1);
+        assert_eq!(sum.count_with(|v| **v == 1), 4);
+    }
 }